@@ -0,0 +1,32 @@
+// remaps Ctrl+hjkl to the arrow keys in every xterm, without a config file
+use x11_key_remapper::key_map::{Key, KeyMap};
+use x11_key_remapper::rebind;
+use x11_key_remapper::Error;
+
+fn main() {
+    let mut key_map = KeyMap::new();
+    key_map
+        .bind("Ctrl+43".parse().unwrap(), Key { code: 113, state: 0 })
+        .bind("Ctrl+44".parse().unwrap(), Key { code: 116, state: 0 })
+        .bind("Ctrl+45".parse().unwrap(), Key { code: 111, state: 0 })
+        .bind("Ctrl+46".parse().unwrap(), Key { code: 114, state: 0 });
+
+    for (from, to) in key_map.iter() {
+        println!("{} -> {}", from, to);
+    }
+
+    let result = rebind::rebind(|info| match info.class {
+        Some("XTerm") => Some(key_map.clone()),
+        _ => None,
+    });
+
+    // there is no X server to remap in, which an application could
+    // handle by running without remapping
+    match result {
+        Err(Error::DisplayConnection { display }) => {
+            println!("could not connect to display {:?}", display)
+        }
+        Err(error) => eprintln!("error: {:?}", error),
+        Ok(()) => (),
+    }
+}
@@ -1,10 +1,13 @@
-use std::process::{Child, Command};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
 use std::thread;
 
+use super::error::Error;
+
 pub struct ChildProcessState {
     // an atomic bool can save us from the overhead of using
     // a mutex as all we are trying to do is update the state of a bool
@@ -22,12 +25,12 @@ impl ChildProcessState {
     }
 }
 
-pub fn spawn_child(mut command: Command) -> Result<ChildProcessState, ()> {
+pub fn spawn_child(mut command: Command) -> Result<ChildProcessState, Error> {
     // spawn the child and receive its id once it
     // returns
     let mut child = match command.spawn() {
         Ok(child) => child,
-        Err(_) => return Err(()),
+        Err(error) => return Err(Error::SpawnChild(error)),
     };
     let child_pid = child.id();
 
@@ -37,7 +40,7 @@ pub fn spawn_child(mut command: Command) -> Result<ChildProcessState, ()> {
     let child_exited = Arc::new(AtomicBool::new(false));
     let child_exited_clone = Arc::clone(&child_exited);
     thread::spawn(move || {
-        if let Err(_) = child.wait() {
+        if child.wait().is_err() {
             return Err(());
         }
 
@@ -51,3 +54,46 @@ pub fn spawn_child(mut command: Command) -> Result<ChildProcessState, ()> {
         pid: child_pid,
     })
 }
+
+// the commands bindings run are not waited on by a thread each, they are
+// reaped when a SIGCHLD arrives instead. Only these children are waited
+// for, waiting for any child would take the exit of the wrapped process
+// from the thread that waits for it
+#[derive(Default)]
+pub struct Commands {
+    running: Vec<Child>,
+}
+
+impl Commands {
+    // runs the command line with sh in its own process group, so that
+    // a ctrl+c in the terminal of the remapper does not reach it
+    pub fn spawn(&mut self, command_line: &str, env: &[(&str, String)]) -> Result<u32, Error> {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(command_line)
+            .stdin(Stdio::null())
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .process_group(0);
+
+        let child = command.spawn().map_err(Error::SpawnChild)?;
+        let pid = child.id();
+        self.running.push(child);
+        Ok(pid)
+    }
+
+    // returns the pid and exit status of every command that exited
+    pub fn reap(&mut self) -> Vec<(u32, ExitStatus)> {
+        let mut exited = Vec::new();
+        self.running.retain_mut(|child| match child.try_wait() {
+            Ok(Some(status)) => {
+                exited.push((child.id(), status));
+                false
+            }
+            Ok(None) => true,
+            // the child was already waited for
+            Err(_) => false,
+        });
+        exited
+    }
+}
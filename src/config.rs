@@ -0,0 +1,587 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
+
+use indexmap::IndexMap;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use super::key_map::{self, Key, KeyMap, KeyMapParseError, KeyMapWarning, Parser, Selection, Statement, Target, Transform};
+use super::rebind::{PauseKeys, WindowInfo};
+
+// the schema for the structured formats, so editors can offer completion
+// for both the toml and the json files
+pub const SCHEMA: &str = include_str!("../config.schema.json");
+
+// the structured config exactly as it is written in a toml or json file,
+// the bindings are kept in the same syntax as the rebind format
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSource {
+    #[serde(default, skip_serializing_if = "PauseSource::is_empty")]
+    pub pause: PauseSource,
+    #[serde(default, rename = "section")]
+    pub sections: Vec<SectionSource>,
+}
+
+// the keys that turn remapping off and on again, for the focused
+// window or for every window
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PauseSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global: Option<String>,
+}
+
+impl PauseSource {
+    fn is_empty(&self) -> bool {
+        self.window.is_none() && self.global.is_none()
+    }
+}
+
+// the injection method and other per section options are not supported
+// yet, every section sends its keys with XSendEvent
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SectionSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, rename = "match")]
+    pub filter: WindowFilter,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub define: BTreeMap<String, String>,
+    #[serde(default, rename = "let", skip_serializing_if = "BTreeMap::is_empty")]
+    pub modifiers: BTreeMap<String, String>,
+    // kept in the order they are written, so that a later binding wins
+    // over an earlier one for the same key the way it does in a rebind file
+    #[serde(default)]
+    pub bindings: IndexMap<String, TargetSource>,
+}
+
+// a key in the rebind syntax, `{ text = "..." }` for text that is typed,
+// `{ exec = "..." }` for a command or `{ paste = "clipboard" }` for a
+// selection. The text and the command are not quoted or escaped, as the
+// format already does that
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(untagged)]
+pub enum TargetSource {
+    Key(String),
+    Text { text: String },
+    Exec { exec: String },
+    Paste {
+        paste: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        transforms: Vec<TransformSource>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        with: Option<String>,
+    },
+}
+
+// `"trim"`, `"plain"` or `{ replace = { pattern = "...", with = "..." } }`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum TransformSource {
+    Trim,
+    Plain,
+    Replace { pattern: String, with: String },
+}
+
+// which windows a section applies to, every field that is set has to match
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WindowFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Section {
+    pub name: Option<String>,
+    pub filter: WindowFilter,
+    pub key_map: KeyMap,
+    // what the bindings of a structured section would be warned about in
+    // a rebind file, for check
+    pub warnings: Vec<KeyMapWarning>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Config {
+    pub pause: PauseKeys,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    KeyMap(KeyMapParseError),
+    InvalidSection {
+        section: usize,
+        entry: String,
+        error: KeyMapParseError,
+    },
+    InvalidPauseKey {
+        entry: String,
+        error: KeyMapParseError,
+    },
+    // the pid given next to a rebind file on the command line
+    InvalidPid { pid: String },
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(error: std::io::Error) -> ConfigError {
+        ConfigError::IoError(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> ConfigError {
+        ConfigError::Toml(error)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(error: serde_json::Error) -> ConfigError {
+        ConfigError::Json(error)
+    }
+}
+
+impl From<KeyMapParseError> for ConfigError {
+    fn from(error: KeyMapParseError) -> ConfigError {
+        ConfigError::KeyMap(error)
+    }
+}
+
+impl WindowFilter {
+    pub fn matches(&self, info: &WindowInfo) -> bool {
+        let matches_class = matches_filter(self.class.as_deref(), info.class);
+        let matches_pid = matches_filter(self.pid, info.pid);
+        matches_class && matches_pid
+    }
+}
+
+fn matches_filter<P: PartialEq>(filter: Option<P>, value: Option<P>) -> bool {
+    if let Some(filter_val) = filter {
+        match value {
+            Some(value) => value == filter_val,
+            None => false
+        }
+    }
+    else {
+        true
+    }
+}
+
+impl Config {
+    // picks the loader based on the extension, anything that is not toml
+    // or json is treated as a rebind file that applies to every window
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Config::from_toml(&text),
+            Some("json") => Config::from_json(&text),
+            _ => {
+                let key_map = KeyMap::from_stream(text.as_bytes())?;
+                Ok(Config::single(WindowFilter::default(), key_map))
+            }
+        }
+    }
+
+    // errors point at the line of the entry they are in
+    pub fn from_toml(text: &str) -> Result<Config, ConfigError> {
+        let source: ConfigSource = toml::from_str(text)?;
+        let lines = toml::from_str::<SpannedConfig>(text)
+            .map(|spanned| EntryLines::new(text, spanned))
+            .unwrap_or_default();
+        Config::build(&source, &lines)
+    }
+
+    // serde_json does not keep where values are, so the errors of a json
+    // config only have the section and the entry, with line 0
+    pub fn from_json(text: &str) -> Result<Config, ConfigError> {
+        let source: ConfigSource = serde_json::from_str(text)?;
+        Config::from_source(&source)
+    }
+
+    pub fn from_source(source: &ConfigSource) -> Result<Config, ConfigError> {
+        Config::build(source, &EntryLines::default())
+    }
+
+    fn build(source: &ConfigSource, lines: &EntryLines) -> Result<Config, ConfigError> {
+        let mut sections = Vec::new();
+        for (i, section) in source.sections.iter().enumerate() {
+            sections.push(build_section(i, section, lines)?);
+        }
+
+        let pause_key = |entry: &str, value: Option<&String>| {
+            parse_pause_key(entry, value.map(|value| value.as_str()), lines.pause(entry))
+        };
+        let pause = PauseKeys {
+            window: pause_key("window", source.pause.window.as_ref())?,
+            global: pause_key("global", source.pause.global.as_ref())?,
+        };
+
+        Ok(Config { pause, sections })
+    }
+
+    pub fn single(filter: WindowFilter, key_map: KeyMap) -> Config {
+        Config {
+            pause: PauseKeys::default(),
+            sections: vec![Section {
+                name: None,
+                filter,
+                key_map,
+                warnings: Vec::new(),
+            }],
+        }
+    }
+
+    // the first section that matches wins, so more specific sections
+    // should be written before the general ones
+    pub fn section_for(&self, info: &WindowInfo) -> Option<&Section> {
+        self.sections.iter().find(|section| section.filter.matches(info))
+    }
+
+    // the name of the section that matches, or its position in the
+    // file for sections without a name
+    pub fn section_label_for(&self, info: &WindowInfo) -> Option<String> {
+        let i = self.sections.iter().position(|section| section.filter.matches(info))?;
+        match &self.sections[i].name {
+            Some(name) => Some(name.clone()),
+            None => Some(format!("section {}", i)),
+        }
+    }
+
+    pub fn key_map_for(&self, info: &WindowInfo) -> Option<KeyMap> {
+        self.section_for(info).map(|section| section.key_map.clone())
+    }
+
+    pub fn section_named(&self, name: &str) -> Option<&Section> {
+        self.sections
+            .iter()
+            .find(|section| section.name.as_deref() == Some(name))
+    }
+
+    // the structured form of the config, with the bindings written out
+    // one key at a time as aliases are not kept after parsing
+    pub fn to_source(&self) -> ConfigSource {
+        self.to_source_named(|_| None)
+    }
+
+    // the same, with the keycodes that name_of has a name for defined in
+    // every section and written as that name. The pause keys are outside
+    // of the sections and keep their numbers
+    pub fn to_source_named(&self, name_of: impl Fn(u32) -> Option<String>) -> ConfigSource {
+        let sections = self
+            .sections
+            .iter()
+            .map(|section| {
+                let names = section.key_map.code_names(&name_of);
+                SectionSource {
+                    name: section.name.clone(),
+                    filter: section.filter.clone(),
+                    define: names.iter().map(|(code, name)| (name.clone(), code.to_string())).collect(),
+                    bindings: section
+                        .key_map
+                        .bindings()
+                        .into_iter()
+                        .map(|(from, to)| (from.to_named_string(&names), target_source(to, &names)))
+                        .collect(),
+                    ..SectionSource::default()
+                }
+            })
+            .collect();
+
+        let pause = PauseSource {
+            window: self.pause.window.map(|key| key.to_string()),
+            global: self.pause.global.map(|key| key.to_string()),
+        };
+
+        ConfigSource { pause, sections }
+    }
+}
+
+fn target_source(target: &Target, names: &BTreeMap<u32, String>) -> TargetSource {
+    match target {
+        Target::Key(key) => TargetSource::Key(key.to_named_string(names)),
+        Target::Text(text) => TargetSource::Text { text: text.clone() },
+        Target::Exec(command) => TargetSource::Exec { exec: command.clone() },
+        Target::Paste(paste) => TargetSource::Paste {
+            paste: paste.selection.name().to_string(),
+            transforms: paste.transforms.iter().map(transform_source).collect(),
+            with: paste.key.map(|key| key.to_named_string(names)),
+        },
+    }
+}
+
+fn transform_source(transform: &Transform) -> TransformSource {
+    match transform {
+        Transform::Trim => TransformSource::Trim,
+        Transform::Plain => TransformSource::Plain,
+        Transform::Replace { pattern, replacement } => TransformSource::Replace {
+            pattern: pattern.clone(),
+            with: replacement.clone(),
+        },
+    }
+}
+
+fn transform(source: &TransformSource) -> Transform {
+    match source {
+        TransformSource::Trim => Transform::Trim,
+        TransformSource::Plain => Transform::Plain,
+        TransformSource::Replace { pattern, with } => Transform::Replace {
+            pattern: pattern.clone(),
+            replacement: with.clone(),
+        },
+    }
+}
+
+fn parse_pause_key(entry: &str, value: Option<&str>, line_number: usize) -> Result<Option<Key>, ConfigError> {
+    match value {
+        Some(value) => match key_map::parse_key_at(value, line_number) {
+            Ok(key) => Ok(Some(key)),
+            Err(error) => Err(ConfigError::InvalidPauseKey {
+                entry: entry.to_string(),
+                error,
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
+// the keys of a toml config with where they are, read next to the
+// ConfigSource as it can not hold the spans itself and still be read
+// from json. Everything else is left to ConfigSource to check
+type SpannedEntries = BTreeMap<Spanned<String>, IgnoredAny>;
+
+#[derive(Deserialize, Default)]
+struct SpannedConfig {
+    #[serde(default)]
+    pause: SpannedEntries,
+    #[serde(default, rename = "section")]
+    sections: Vec<SpannedSection>,
+}
+
+#[derive(Deserialize, Default)]
+struct SpannedSection {
+    #[serde(default)]
+    define: SpannedEntries,
+    #[serde(default, rename = "let")]
+    modifiers: SpannedEntries,
+    #[serde(default)]
+    bindings: SpannedEntries,
+}
+
+// the line every entry starts on, 0 for the entries it does not know of
+#[derive(Default)]
+struct EntryLines {
+    pause: HashMap<String, usize>,
+    sections: Vec<HashMap<(&'static str, String), usize>>,
+}
+
+impl EntryLines {
+    fn new(text: &str, spanned: SpannedConfig) -> EntryLines {
+        let line_of = |entries: SpannedEntries| {
+            entries
+                .into_keys()
+                .map(|key| {
+                    let line = text[..key.span().start].matches('\n').count() + 1;
+                    (key.into_inner(), line)
+                })
+                .collect::<Vec<(String, usize)>>()
+        };
+
+        let sections = spanned
+            .sections
+            .into_iter()
+            .map(|section| {
+                let mut lines = HashMap::new();
+                for (table, entries) in [
+                    ("define", section.define),
+                    ("let", section.modifiers),
+                    ("bindings", section.bindings),
+                ] {
+                    for (entry, line) in line_of(entries) {
+                        lines.insert((table, entry), line);
+                    }
+                }
+                lines
+            })
+            .collect();
+
+        EntryLines {
+            pause: line_of(spanned.pause).into_iter().collect(),
+            sections,
+        }
+    }
+
+    fn pause(&self, entry: &str) -> usize {
+        self.pause.get(entry).copied().unwrap_or(0)
+    }
+
+    fn entry(&self, section: usize, table: &'static str, entry: &str) -> usize {
+        self.sections
+            .get(section)
+            .and_then(|lines| lines.get(&(table, entry.to_string())))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl ConfigSource {
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config source is always valid toml")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("config source is always valid json")
+    }
+}
+
+fn build_section(index: usize, section: &SectionSource, lines: &EntryLines) -> Result<Section, ConfigError> {
+    let mut parser = Parser::default();
+
+    // the tables do not keep the order they were written in, so an alias
+    // that uses another alias may come before it. Keep defining whatever
+    // can be defined until nothing changes, and report what is left over
+    let mut pending: Vec<(&'static str, &String, &String)> = section
+        .define
+        .iter()
+        .map(|(name, body)| ("define", name, body))
+        .chain(section.modifiers.iter().map(|(name, body)| ("let", name, body)))
+        .collect();
+
+    while !pending.is_empty() {
+        let mut last_error = None;
+        let before = pending.len();
+        pending.retain(|&(kind, name, body)| match parser.define(kind, name, body, lines.entry(index, kind, name)) {
+            Ok(()) => false,
+            Err(error) => {
+                last_error = Some((name.clone(), error));
+                true
+            }
+        });
+
+        if pending.len() == before {
+            let (entry, error) = last_error.expect("pending definitions always fail");
+            return Err(ConfigError::InvalidSection {
+                section: index,
+                entry,
+                error,
+            });
+        }
+    }
+
+    for (from, to) in &section.bindings {
+        let line_number = lines.entry(index, "bindings", from);
+        let bound = match to {
+            TargetSource::Key(to) => parser.bind(from, to, line_number),
+            TargetSource::Text { text } => parser.bind_text(from, text, line_number),
+            TargetSource::Exec { exec } => parser.bind_target(from, Target::Exec(exec.clone()), line_number),
+            TargetSource::Paste {
+                paste,
+                transforms,
+                with,
+            } => match Selection::from_name(paste) {
+                Some(selection) => {
+                    let transforms = transforms.iter().map(transform).collect();
+                    parser.bind_paste(from, selection, transforms, with.as_deref(), line_number)
+                }
+                None => Err(KeyMapParseError::UnknownSelection { line_number }),
+            },
+        };
+        if let Err(error) = bound {
+            return Err(ConfigError::InvalidSection {
+                section: index,
+                entry: from.clone(),
+                error,
+            });
+        }
+    }
+
+    Ok(Section {
+        name: section.name.clone(),
+        filter: section.filter.clone(),
+        warnings: std::mem::take(&mut parser.warnings),
+        key_map: parser.finish(),
+    })
+}
+
+// a key that is bound again moves to the end, so it still comes after the
+// bindings it replaced part of, such as a list that it is in
+fn insert_binding(bindings: &mut IndexMap<String, TargetSource>, from: &str, to: TargetSource) {
+    bindings.shift_remove(from);
+    bindings.insert(from.to_string(), to);
+}
+
+// turns a rebind file into a single section of the structured format. The
+// file is parsed along the way so that a broken file is never converted
+pub fn convert_rebind(
+    mut stream: impl BufRead,
+    filter: WindowFilter,
+) -> Result<ConfigSource, KeyMapParseError> {
+    let mut parser = Parser::default();
+    let mut section = SectionSource {
+        filter,
+        ..SectionSource::default()
+    };
+
+    let mut buffer = String::new();
+    let mut line_number = 0;
+    while stream.read_line(&mut buffer)? != 0 {
+        line_number += 1;
+
+        if let Some(statement) = key_map::parse_statement(&buffer, line_number)? {
+            parser.apply(&statement, line_number)?;
+
+            match statement {
+                Statement::Define { kind: "define", name, body } => {
+                    section.define.insert(name.to_string(), body.to_string());
+                }
+                Statement::Define { name, body, .. } => {
+                    section.modifiers.insert(name.to_string(), body.to_string());
+                }
+                Statement::Bind { from, to } => {
+                    let to = match key_map::parse_text(to, line_number)? {
+                        Some(text) => TargetSource::Text { text },
+                        None => TargetSource::Key(to.to_string()),
+                    };
+                    insert_binding(&mut section.bindings, from, to);
+                }
+                Statement::Exec { from, command } => {
+                    let exec = key_map::parse_command(command, line_number)?;
+                    insert_binding(&mut section.bindings, from, TargetSource::Exec { exec });
+                }
+                Statement::Paste {
+                    from,
+                    selection,
+                    transforms,
+                    with,
+                } => {
+                    let paste = TargetSource::Paste {
+                        paste: selection.name().to_string(),
+                        transforms: transforms.iter().map(transform_source).collect(),
+                        with: with.map(|with| with.to_string()),
+                    };
+                    insert_binding(&mut section.bindings, from, paste);
+                }
+            }
+        }
+
+        buffer.clear();
+    }
+
+    Ok(ConfigSource {
+        pause: PauseSource::default(),
+        sections: vec![section],
+    })
+}
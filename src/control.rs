@@ -0,0 +1,105 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use super::xbridge::WindowHandle;
+
+// the commands of the control socket. Every connection sends one command
+// on one line, and reads the reply until the socket is closed. A reply
+// that failed starts with "error: "
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    // every wrapped window, one per line
+    List,
+    // remapping for a single window, or for every window without one
+    Enable(Option<WindowHandle>),
+    Disable(Option<WindowHandle>),
+    // uses the bindings of the named section for every window, or goes
+    // back to the section that matched each window without a name
+    Layer(Option<String>),
+    Reload,
+    // gives the window back, it is not wrapped again
+    Release(WindowHandle),
+    Stats,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (&name, arguments) = match words.split_first() {
+            Some(split) => split,
+            None => return Err("no command given".to_string()),
+        };
+
+        let command = match (name, arguments) {
+            ("list", []) => Command::List,
+            ("enable", []) => Command::Enable(None),
+            ("enable", [window]) => Command::Enable(Some(parse_window(window)?)),
+            ("disable", []) => Command::Disable(None),
+            ("disable", [window]) => Command::Disable(Some(parse_window(window)?)),
+            ("layer", []) => Command::Layer(None),
+            ("layer", [name]) => Command::Layer(Some(name.to_string())),
+            ("reload", []) => Command::Reload,
+            ("release", [window]) => Command::Release(parse_window(window)?),
+            ("stats", []) => Command::Stats,
+            ("list" | "enable" | "disable" | "layer" | "reload" | "release" | "stats", _) => {
+                return Err(format!("wrong arguments for {}", name))
+            }
+            _ => return Err(format!("unknown command: {}", name)),
+        };
+
+        Ok(command)
+    }
+}
+
+// windows are given the way xwininfo and xprop print them, in hex with
+// 0x in front, or in decimal
+pub fn parse_window(window: &str) -> Result<WindowHandle, String> {
+    let parsed = match window.strip_prefix("0x") {
+        Some(hex) => WindowHandle::from_str_radix(hex, 16),
+        None => window.parse::<WindowHandle>(),
+    };
+
+    parsed.map_err(|_| format!("not a window: {}", window))
+}
+
+// $XDG_RUNTIME_DIR is only readable by the user. When it is not set the
+// socket goes into a directory of the user's own in /tmp, which the
+// remapper creates only readable by the user
+pub fn default_socket_path() -> PathBuf {
+    let name = match std::env::var("DISPLAY") {
+        Ok(display) => format!("x11-key-remapper{}.sock", display.replace(['/', ':'], "-")),
+        Err(_) => "x11-key-remapper.sock".to_string(),
+    };
+
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join(name),
+        None => {
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir().join(format!("x11-key-remapper-{}", uid)).join(name)
+        }
+    }
+}
+
+// reads the command a client sent, a client that does not send
+// a whole line in time is given up on
+pub fn read_command(stream: &UnixStream) -> io::Result<String> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(1)))?;
+
+    let mut line = String::new();
+    BufReader::new(stream).take(4096).read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+// sends a command to a running remapper and returns its reply
+pub fn send(path: &Path, command: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{}", command)?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
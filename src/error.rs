@@ -0,0 +1,80 @@
+use std::fmt;
+
+use super::config::ConfigError;
+use super::key_map::KeyMapParseError;
+use super::xbridge::XError;
+
+// every error that can stop the remapper, so an application that embeds
+// it can decide what to do instead of the process exiting
+#[derive(Debug)]
+pub enum Error {
+    // libX11 could not be loaded
+    Xlib(x11_dl::error::OpenError),
+    // display is the DISPLAY that was tried, if it was set
+    DisplayConnection { display: Option<String> },
+    // an atom the remapper can not work without could not be interned
+    MissingAtom { name: &'static str },
+    // the X server refused one of the requests
+    Protocol(XError),
+    // text has a character that is not on the keyboard, and there is no
+    // keycode left to put it on
+    NoKeycode { character: char },
+    SpawnChild(std::io::Error),
+    Config(ConfigError),
+    // waiting for events, signals or files failed
+    EventLoop(std::io::Error),
+}
+
+impl Error {
+    // whether the error is only about a window that no longer exists,
+    // which happens whenever a window closes while we are working on it
+    pub fn is_bad_window(&self) -> bool {
+        matches!(self, Error::Protocol(error) if error.is_bad_window())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Xlib(error) => write!(f, "could not load libX11: {}", error),
+            Error::DisplayConnection { display: Some(display) } => {
+                write!(f, "could not connect to display {}", display)
+            }
+            Error::DisplayConnection { display: None } => {
+                write!(f, "could not connect to the display, DISPLAY is not set")
+            }
+            Error::MissingAtom { name } => write!(f, "could not intern the {} atom", name),
+            Error::Protocol(error) => write!(f, "the X server refused a request: {:?}", error),
+            Error::NoKeycode { character } => write!(f, "no keycode left to type {:?}", character),
+            Error::SpawnChild(error) => write!(f, "could not start the command: {}", error),
+            Error::Config(error) => write!(f, "could not load the config: {:?}", error),
+            Error::EventLoop(error) => write!(f, "waiting for events failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<x11_dl::error::OpenError> for Error {
+    fn from(error: x11_dl::error::OpenError) -> Error {
+        Error::Xlib(error)
+    }
+}
+
+impl From<XError> for Error {
+    fn from(error: XError) -> Error {
+        Error::Protocol(error)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(error: ConfigError) -> Error {
+        Error::Config(error)
+    }
+}
+
+impl From<KeyMapParseError> for Error {
+    fn from(error: KeyMapParseError) -> Error {
+        Error::Config(ConfigError::KeyMap(error))
+    }
+}
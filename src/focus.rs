@@ -0,0 +1,109 @@
+use super::window_table::WindowTable;
+use super::xbridge::WindowHandle;
+
+// the focus of no window, and the focus of whatever window the pointer is in
+const NO_FOCUS: WindowHandle = 0;
+const POINTER_ROOT: WindowHandle = 1;
+
+// the input models of ICCCM 4.1.7. They decide whether a window is given
+// focus with XSetInputFocus, with a WM_TAKE_FOCUS message, or with both
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FocusModel {
+    NoInput,
+    Passive,
+    LocallyActive,
+    GloballyActive,
+}
+
+impl FocusModel {
+    // input is the input field of WM_HINTS, or None when the window has not
+    // set it. Like most window managers, a missing hint is taken as the
+    // window wanting input, as otherwise it could never be typed in
+    pub fn from_hints(input: Option<bool>, takes_focus: bool) -> FocusModel {
+        match (input.unwrap_or(true), takes_focus) {
+            (false, false) => FocusModel::NoInput,
+            (true, false) => FocusModel::Passive,
+            (true, true) => FocusModel::LocallyActive,
+            (false, true) => FocusModel::GloballyActive,
+        }
+    }
+
+    pub fn sets_input_focus(self) -> bool {
+        matches!(self, FocusModel::Passive | FocusModel::LocallyActive)
+    }
+
+    pub fn sends_take_focus(self) -> bool {
+        matches!(self, FocusModel::LocallyActive | FocusModel::GloballyActive)
+    }
+}
+
+// the requests focusing is made of. XBridge sends them to the display, so
+// the decisions below can also be made against windows that only exist in
+// a test
+pub trait FocusBackend {
+    fn focus_model(&self, window: WindowHandle) -> FocusModel;
+    fn set_input_focus(&self, window: WindowHandle, time: u64);
+    fn send_take_focus(&self, window: WindowHandle, time: u64);
+    // the window that has the focus, which can also be NO_FOCUS or
+    // POINTER_ROOT
+    fn input_focus(&self) -> WindowHandle;
+    // None for a window whose parent is the root, or that is gone
+    fn parent(&self, window: WindowHandle) -> Option<WindowHandle>;
+}
+
+// gives focus to the window the way its focus model asks for, time should
+// be the timestamp of the event that caused the focus change, as globally
+// active clients rely on it. 0 is CurrentTime
+pub fn focus_window(backend: &impl FocusBackend, window: WindowHandle, time: u64) {
+    let model = backend.focus_model(window);
+
+    if model.sets_input_focus() {
+        backend.set_input_focus(window, time);
+    }
+
+    if model.sends_take_focus() {
+        backend.send_take_focus(window, time);
+    }
+}
+
+// whether the focus is already on the window or on one of its
+// descendants, in which case it should not be taken away again
+pub fn has_focus_within(backend: &impl FocusBackend, window: WindowHandle) -> bool {
+    is_within(backend, backend.input_focus(), window)
+}
+
+// whether the window is the ancestor or one of its descendants
+pub fn is_within(backend: &impl FocusBackend, mut window: WindowHandle, ancestor: WindowHandle) -> bool {
+    // walk up from the window until the root is reached
+    while window != NO_FOCUS && window != POINTER_ROOT {
+        if window == ancestor {
+            return true;
+        }
+
+        match backend.parent(window) {
+            Some(parent) => window = parent,
+            None => return false,
+        }
+    }
+
+    false
+}
+
+// the parent only exists to grab keys, so whenever the window manager
+// focuses it, or asks it to take focus, the focus is passed on to the
+// child. If the child already has focus it is left alone, so the window
+// manager is not fought with. Returns the child that was focused
+pub fn pass_focus(
+    backend: &impl FocusBackend,
+    windows: &WindowTable,
+    parent: WindowHandle,
+    time: Option<u64>,
+) -> Option<WindowHandle> {
+    let child = windows.wrapped_child(parent)?;
+    if has_focus_within(backend, child) {
+        return None;
+    }
+
+    focus_window(backend, child, time.unwrap_or(0));
+    Some(child)
+}
@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use super::config::{Config, ConfigError};
+use super::error::Error;
+use super::key_map::{Key, KeyMap, KeyMapWarning, Target};
+use super::reactor::Reactor;
+use super::rebind::WindowQuery;
+use super::xbridge::{WindowHandle, XBridge, XBridgeEvent};
+
+// opens a window and prints every key that is pressed or released in it,
+// along with what the config would remap it to when one is given
+pub fn keys(config: Option<&Config>) -> Result<(), Error> {
+    let mut x = XBridge::init(None)?;
+    let window = x.create_input_window("x11-key-remapper keys")?;
+    let mut reactor: Reactor<()> = Reactor::new(x.connection_fd());
+
+    println!("press keys in the window, close it to exit");
+    loop {
+        while let Some(event) = x.next_event() {
+            match event {
+                XBridgeEvent::KeyPress { parent, key, .. } if parent == window => {
+                    print_key(&x, true, key, config);
+                }
+                XBridgeEvent::KeyRelease { window: released, key } if released == window => {
+                    print_key(&x, false, key, config);
+                }
+                XBridgeEvent::DestroyRequest { window: closed } if closed == window => {
+                    return x.destroy_window(window);
+                }
+                _ => (),
+            }
+        }
+
+        reactor.wait().map_err(Error::EventLoop)?;
+    }
+}
+
+fn print_key(x: &XBridge, pressed: bool, key: Key, config: Option<&Config>) {
+    let action = if pressed { "press" } else { "release" };
+    let keysym = x.keysym_name(key).unwrap_or_else(|| "-".to_string());
+    let modifiers = match key.modifier_names() {
+        names if names.is_empty() => "-".to_string(),
+        names => names.join("+"),
+    };
+
+    println!(
+        "{:<8} code {:<4} state 0x{:<5x} keysym {:<12} modifiers {:<12} rebind {}",
+        action, key.code, key.state, keysym, modifiers, key
+    );
+
+    // only presses are remapped
+    let config = match config {
+        Some(config) if pressed => config,
+        _ => return,
+    };
+
+    let mut remapped = false;
+    for (i, section) in config.sections.iter().enumerate() {
+        if let Some(to) = section.key_map.target(key) {
+            let name = match &section.name {
+                Some(name) => name.clone(),
+                None => format!("section {}", i),
+            };
+            println!("    -> {} in {}", to, name);
+            remapped = true;
+        }
+    }
+
+    if config.pause.pauses_window(key) || config.pause.pauses_all(key) {
+        println!("    pauses remapping");
+    } else if !remapped {
+        println!("    not remapped");
+    }
+}
+
+// prints every client window the way the filter sees it, and when a config
+// is given which of them it would wrap and with which section
+pub fn list_windows(config: Option<&Config>) -> Result<(), Error> {
+    let mut x = XBridge::init(None)?;
+
+    let mut header = vec!["WINDOW", "PID", "TYPE", "KIND", "CLASS", "INSTANCE"];
+    if config.is_some() {
+        header.push("SECTION");
+    }
+    header.push("TITLE");
+    let mut rows = vec![header.iter().map(|name| name.to_string()).collect::<Vec<String>>()];
+
+    for window in x.client_windows()? {
+        // a window that closes while it is being listed is left out
+        let row = match window_row(&mut x, window, config) {
+            Ok(row) => row,
+            Err(error) if error.is_bad_window() => continue,
+            Err(error) => return Err(error),
+        };
+        rows.push(row);
+    }
+
+    print_table(&rows);
+    Ok(())
+}
+
+fn window_row(x: &mut XBridge, window: WindowHandle, config: Option<&Config>) -> Result<Vec<String>, Error> {
+    let query = WindowQuery::read(x, window)?;
+    let info = query.info();
+    let instance = x.get_window_instance(window)?;
+    let title = x.get_window_title(window)?;
+
+    let text = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let mut row = vec![
+        format!("0x{:x}", window),
+        text(info.pid.map(|pid| pid.to_string())),
+        text(info.window_type.map(|window_type| window_type.name().to_string())),
+        format!("{:?}", info.kind),
+        text(info.class.map(|class| class.to_string())),
+        text(instance.map(|instance| instance.to_string_lossy().into_owned())),
+    ];
+
+    if let Some(config) = config {
+        // the same checks rebind makes before it wraps a window
+        let section = match info.kind.is_wrapped() {
+            true => config.section_label_for(&info),
+            false => None,
+        };
+        row.push(text(section));
+    }
+
+    row.push(text(title));
+    Ok(row)
+}
+
+// every column is as wide as its widest value, except for the last one
+fn print_table(rows: &[Vec<String>]) {
+    let columns = rows.first().map_or(0, |header| header.len());
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or(0))
+        .collect();
+
+    for row in rows {
+        let mut line = String::new();
+        for (column, value) in row.iter().enumerate() {
+            if column + 1 == columns {
+                line.push_str(value);
+            } else {
+                line.push_str(&format!("{:<width$}  ", value, width = widths[column]));
+            }
+        }
+        println!("{}", line);
+    }
+}
+
+// prints a config the way it is serialized, with the keycodes written as
+// the names of their keysyms when there is a display to look them up on
+pub fn format(path: &Path) -> Result<(), Error> {
+    let x = match std::env::var_os("DISPLAY") {
+        Some(_) => XBridge::init(None).ok(),
+        None => None,
+    };
+    let name_of = |code: u32| x.as_ref()?.keysym_name(Key { code, state: 0 });
+
+    let config = Config::from_path(path)?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => print!("{}", config.to_source_named(name_of).to_toml()),
+        Some("json") => println!("{}", config.to_source_named(name_of).to_json()),
+        _ => {
+            let key_map = &config.sections[0].key_map;
+            print!("{}", key_map.to_rebind_named(&key_map.code_names(name_of)));
+        }
+    }
+
+    Ok(())
+}
+
+// reports every problem in a config file along with the bindings it ends up
+// with. Returns whether the file passed, a file with warnings only fails
+// when strict is set. Keysym names are shown when there is a display, a
+// check that runs without one, such as in a hook, never loads Xlib
+pub fn check(path: &Path, strict: bool) -> Result<bool, Error> {
+    let x = match std::env::var_os("DISPLAY") {
+        Some(_) => XBridge::init(None).ok(),
+        None => None,
+    };
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") | Some("json") => check_structured(path, strict, x.as_ref()),
+        _ => check_rebind(path, strict, x.as_ref()),
+    }
+}
+
+fn check_rebind(path: &Path, strict: bool, x: Option<&XBridge>) -> Result<bool, Error> {
+    let file = BufReader::new(File::open(path).map_err(ConfigError::IoError)?);
+    let checked = KeyMap::check_stream(file)?;
+
+    let rows = checked
+        .bindings
+        .iter()
+        .map(|(line_number, from, to)| (line_number.to_string(), *from, to.clone()));
+    print_bindings("LINE", rows, x);
+
+    for error in &checked.errors {
+        println!("error: {:?}", error);
+    }
+    for warning in &checked.warnings {
+        println!("warning: {}", describe_warning(warning));
+    }
+
+    println!(
+        "{} bindings, {} errors, {} warnings",
+        checked.bindings.len(),
+        checked.errors.len(),
+        checked.warnings.len()
+    );
+
+    let failed = !checked.errors.is_empty() || (strict && !checked.warnings.is_empty());
+    Ok(!failed)
+}
+
+// the structured formats are parsed as a whole, so only the first
+// error in them can be reported. The warnings are the same as for a rebind
+// file, by section, with the line of the entry in a toml file and line 0
+// in a json file
+fn check_structured(path: &Path, strict: bool, x: Option<&XBridge>) -> Result<bool, Error> {
+    let config = match Config::from_path(path) {
+        Ok(config) => config,
+        Err(error @ ConfigError::IoError(_)) => return Err(error.into()),
+        Err(error) => {
+            println!("error: {:?}", error);
+            return Ok(false);
+        }
+    };
+
+    for (i, section) in config.sections.iter().enumerate() {
+        let rows = section.key_map.bindings().into_iter();
+        let rows = rows.map(|(from, to)| (i.to_string(), from, to.clone()));
+
+        if let Some(name) = &section.name {
+            println!("section {}: {}", i, name);
+        }
+        print_bindings("SECTION", rows, x);
+    }
+
+    let mut warning_count = 0;
+    for (i, section) in config.sections.iter().enumerate() {
+        for warning in &section.warnings {
+            println!("warning: section {}, {}", i, describe_warning(warning));
+            warning_count += 1;
+        }
+    }
+
+    let binding_count: usize = config.sections.iter().map(|section| section.key_map.bindings().len()).sum();
+    println!("{} bindings, 0 errors, {} warnings", binding_count, warning_count);
+
+    Ok(!(strict && warning_count > 0))
+}
+
+fn print_bindings(first: &str, bindings: impl Iterator<Item = (String, Key, Target)>, x: Option<&XBridge>) {
+    let mut header = vec![first, "FROM", "TO"];
+    if x.is_some() {
+        header.extend(["FROM KEYSYM", "TO KEYSYM"]);
+    }
+    let mut rows = vec![header.iter().map(|name| name.to_string()).collect::<Vec<String>>()];
+
+    for (first, from, to) in bindings {
+        let mut row = vec![first, from.to_string(), to.to_string()];
+        if let Some(x) = x {
+            let name = |key: Key| x.keysym_name(key).unwrap_or_else(|| "-".to_string());
+            let to_name = match &to {
+                Target::Key(to) => name(*to),
+                Target::Text(_) | Target::Exec(_) | Target::Paste(_) => "-".to_string(),
+            };
+            row.extend([name(from), to_name]);
+        }
+        rows.push(row);
+    }
+
+    print_table(&rows);
+}
+
+fn describe_warning(warning: &KeyMapWarning) -> String {
+    match *warning {
+        KeyMapWarning::Duplicate {
+            line_number,
+            first_line,
+            key,
+        } => format!("line {}: {} is bound the same way on line {}", line_number, key, first_line),
+        KeyMapWarning::Conflict {
+            line_number,
+            first_line,
+            key,
+            ref replaced,
+        } => format!(
+            "line {}: {} was bound to {} on line {}, this line replaces it",
+            line_number, key, replaced, first_line
+        ),
+        KeyMapWarning::Unchanged { line_number, key } => {
+            format!("line {}: {} is bound to itself", line_number, key)
+        }
+    }
+}
@@ -1,5 +1,10 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::io::BufRead;
+use std::str::FromStr;
+
+use regex::Regex;
 
 #[derive(Hash, Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Key {
@@ -7,9 +12,86 @@ pub struct Key {
     pub state: u32,
 }
 
-#[derive(Clone)]
+// wildcard bindings are marked with bits above the 16 bits of a core
+// protocol state, so they are never confused with a key that was pressed.
+// `Any+` matches whatever other modifiers are held, `*+` also adds the
+// other modifiers to the key it is bound to
+pub const ANY_MODIFIERS: u32 = 1 << 16;
+pub const KEEP_MODIFIERS: u32 = 1 << 17;
+const WILDCARDS: u32 = ANY_MODIFIERS | KEEP_MODIFIERS;
+
+// a key with this bit is a mouse button, its code is the number of the
+// button. The wheel is buttons 4 to 7, one press for every notch
+pub const MOUSE_BUTTON: u32 = 1 << 18;
+
+// the part of a state the core protocol knows about
+const CORE_STATE: u32 = 0xffff;
+
+// CapsLock and NumLock, which stay on instead of being held down
+pub const LOCK_MODIFIERS: u32 = 0x2 | 0x10;
+
+// words with a meaning in a rebind line, which a key can not be named
+const KEYWORDS: [&str; 4] = ["define", "let", "exec", "paste"];
+
+const SCROLL_NAMES: [(&str, u32); 4] = [
+    ("ScrollUp", 4),
+    ("ScrollDown", 5),
+    ("ScrollLeft", 6),
+    ("ScrollRight", 7),
+];
+
+// what a key is bound to, another key or button, text that is typed
+// one character at a time, a command line that is run, or the contents
+// of a selection that are changed before they are pasted
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Target {
+    Key(Key),
+    Text(String),
+    Exec(String),
+    Paste(Paste),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Selection {
+    Clipboard,
+    Primary,
+}
+
+// what is done to the text of a selection, in the order they are written
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Transform {
+    // removes the whitespace at the start and the end
+    Trim,
+    // removes what is left of the formatting in text that was copied
+    // from a document, see plain_text
+    Plain,
+    // replaces every match of the regex, the replacement can use the
+    // groups of the match as `$1` or `${name}`
+    Replace { pattern: String, replacement: String },
+}
+
+// the selection is read, transformed, and then owned with the new text
+// while the key that pastes it is sent to the window
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Paste {
+    pub selection: Selection,
+    pub transforms: Vec<Transform>,
+    // the key or button that makes the window paste, None for the usual
+    // one of the selection
+    pub key: Option<Key>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
 pub struct KeyMap {
-    map: HashMap<Key, Key>,
+    map: HashMap<Key, Target>,
+}
+
+// the value of a `define` or `let` line, already expanded so that
+// aliases which use other aliases do not need to be resolved again
+#[derive(Clone, Copy, Debug)]
+struct Alias {
+    state: u32,
+    code: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -19,8 +101,52 @@ pub enum KeyMapParseError {
     CouldNotParse { line_number: usize },
     NoKeyPresent { line_number: usize },
     NotValidKey { line_number: usize },
+    // a name that could be an alias, but that no `define` or `let`
+    // above the line defines
+    UnknownAlias { line_number: usize, name: String },
     TooFewArguments { line_number: usize },
     TooManyArguments { line_number: usize },
+    InvalidDefinition { line_number: usize },
+    ReservedName { line_number: usize },
+    NotModifier { line_number: usize },
+    UnclosedList { line_number: usize },
+    ListLengthMismatch { line_number: usize },
+    UnclosedText { line_number: usize },
+    InvalidEscape { line_number: usize },
+    UnknownSelection { line_number: usize },
+    UnknownTransform { line_number: usize },
+    InvalidPattern { line_number: usize },
+}
+
+// lines that parse but most likely do not do what was meant
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum KeyMapWarning {
+    // the same binding was already made on an earlier line
+    Duplicate {
+        line_number: usize,
+        first_line: usize,
+        key: Key,
+    },
+    // the key was bound to something else before, the later binding wins
+    Conflict {
+        line_number: usize,
+        first_line: usize,
+        key: Key,
+        replaced: Target,
+    },
+    // the key is bound to itself, which does nothing
+    Unchanged { line_number: usize, key: Key },
+}
+
+// everything that was found in a rebind file. Parsing goes on after
+// a line that fails, so every broken line is reported at once
+#[derive(Debug)]
+pub struct CheckedKeyMap {
+    pub key_map: KeyMap,
+    // the bindings that are left, with the line that made them
+    pub bindings: Vec<(usize, Key, Target)>,
+    pub errors: Vec<KeyMapParseError>,
+    pub warnings: Vec<KeyMapWarning>,
 }
 
 impl From<std::io::Error> for KeyMapParseError {
@@ -29,9 +155,524 @@ impl From<std::io::Error> for KeyMapParseError {
     }
 }
 
+// a single meaningful line of a rebind file, still in its text form so
+// that it can be applied to a parser or converted to another format
+pub(crate) enum Statement<'a> {
+    Define {
+        kind: &'a str,
+        name: &'a str,
+        body: &'a str,
+    },
+    Bind {
+        from: &'a str,
+        to: &'a str,
+    },
+    // `<key> exec "<command>"`, the command does not have to be quoted
+    // when it is a single word
+    Exec {
+        from: &'a str,
+        command: &'a str,
+    },
+    // `<key> paste <selection> [transform...] [with <key>]`
+    Paste {
+        from: &'a str,
+        selection: Selection,
+        transforms: Vec<Transform>,
+        with: Option<&'a str>,
+    },
+}
+
+// the state that is kept between lines, so that aliases defined on one
+// line can be used by any of the lines after it
+#[derive(Default)]
+pub(crate) struct Parser {
+    map: HashMap<Key, Target>,
+    aliases: HashMap<String, Alias>,
+    lines: HashMap<Key, usize>,
+    pub(crate) warnings: Vec<KeyMapWarning>,
+}
+
+impl Parser {
+    pub(crate) fn apply(
+        &mut self,
+        statement: &Statement,
+        line_number: usize,
+    ) -> Result<(), KeyMapParseError> {
+        match *statement {
+            Statement::Define { kind, name, body } => self.define(kind, name, body, line_number),
+            Statement::Bind { from, to } => self.bind(from, to, line_number),
+            Statement::Exec { from, command } => {
+                let command = parse_command(command, line_number)?;
+                self.bind_target(from, Target::Exec(command), line_number)
+            }
+            Statement::Paste {
+                from,
+                selection,
+                ref transforms,
+                with,
+            } => self.bind_paste(from, selection, transforms.clone(), with, line_number),
+        }
+    }
+
+    pub(crate) fn define(
+        &mut self,
+        kind: &str,
+        name: &str,
+        body: &str,
+        line_number: usize,
+    ) -> Result<(), KeyMapParseError> {
+        let alias = parse_definition(kind, name, body, &self.aliases, line_number)?;
+        self.aliases.insert(name.to_string(), alias);
+        Ok(())
+    }
+
+    pub(crate) fn bind(
+        &mut self,
+        from: &str,
+        to: &str,
+        line_number: usize,
+    ) -> Result<(), KeyMapParseError> {
+        if let Some(text) = parse_text(to, line_number)? {
+            return self.bind_text(from, &text, line_number);
+        }
+
+        let press_keys = self.parse_press_keys(from, line_number)?;
+        let map_keys = parse_list(to, &self.aliases, line_number)?;
+
+        // the key that is sent has to be a real key
+        if map_keys.iter().any(|key| key.is_wildcard()) {
+            return Err(KeyMapParseError::NotValidKey { line_number });
+        }
+
+        // a single target is shared by every key in the list, otherwise
+        // the lists are paired up in order
+        let pairs: Vec<(Key, Key)> = if map_keys.len() == 1 {
+            press_keys.into_iter().map(|press_key| (press_key, map_keys[0])).collect()
+        } else if press_keys.len() == map_keys.len() {
+            press_keys.into_iter().zip(map_keys).collect()
+        } else {
+            return Err(KeyMapParseError::ListLengthMismatch { line_number });
+        };
+
+        for (from, to) in pairs {
+            self.insert(from, Target::Key(to), line_number);
+        }
+
+        Ok(())
+    }
+
+    // the text is already unquoted, every key in the list types all of it
+    pub(crate) fn bind_text(
+        &mut self,
+        from: &str,
+        text: &str,
+        line_number: usize,
+    ) -> Result<(), KeyMapParseError> {
+        self.bind_target(from, Target::Text(text.to_string()), line_number)
+    }
+
+    // every key in the list gets the same target
+    pub(crate) fn bind_target(
+        &mut self,
+        from: &str,
+        target: Target,
+        line_number: usize,
+    ) -> Result<(), KeyMapParseError> {
+        if let Target::Text(text) | Target::Exec(text) = &target {
+            if text.is_empty() {
+                return Err(KeyMapParseError::NoKeyPresent { line_number });
+            }
+        }
+
+        for press_key in self.parse_press_keys(from, line_number)? {
+            self.insert(press_key, target.clone(), line_number);
+        }
+
+        Ok(())
+    }
+
+    // the regexes are only checked here, so that a structured config
+    // gets the same errors as a rebind file
+    pub(crate) fn bind_paste(
+        &mut self,
+        from: &str,
+        selection: Selection,
+        transforms: Vec<Transform>,
+        with: Option<&str>,
+        line_number: usize,
+    ) -> Result<(), KeyMapParseError> {
+        for transform in &transforms {
+            if let Transform::Replace { pattern, .. } = transform {
+                Regex::new(pattern).map_err(|_| KeyMapParseError::InvalidPattern { line_number })?;
+            }
+        }
+
+        let key = match with {
+            Some(with) => {
+                let key = parse_split(with, &self.aliases, line_number)?;
+                if key.is_wildcard() {
+                    return Err(KeyMapParseError::NotValidKey { line_number });
+                }
+                Some(key)
+            }
+            None => None,
+        };
+
+        let paste = Paste {
+            selection,
+            transforms,
+            key,
+        };
+        self.bind_target(from, Target::Paste(paste), line_number)
+    }
+
+    // a key is either matched exactly or by one of the wildcards
+    fn parse_press_keys(&self, from: &str, line_number: usize) -> Result<Vec<Key>, KeyMapParseError> {
+        let press_keys = parse_list(from, &self.aliases, line_number)?;
+        if press_keys.iter().any(|key| key.state & WILDCARDS == WILDCARDS) {
+            return Err(KeyMapParseError::NotValidKey { line_number });
+        }
+
+        Ok(press_keys)
+    }
+
+    fn insert(&mut self, from: Key, to: Target, line_number: usize) {
+        if to == Target::Key(from) {
+            self.warnings.push(KeyMapWarning::Unchanged { line_number, key: from });
+        }
+
+        if let Some(replaced) = self.map.insert(from, to.clone()) {
+            let first_line = self.lines[&from];
+            self.warnings.push(match replaced == to {
+                true => KeyMapWarning::Duplicate {
+                    line_number,
+                    first_line,
+                    key: from,
+                },
+                false => KeyMapWarning::Conflict {
+                    line_number,
+                    first_line,
+                    key: from,
+                    replaced,
+                },
+            });
+        }
+
+        self.lines.insert(from, line_number);
+    }
+
+    pub(crate) fn finish(self) -> KeyMap {
+        KeyMap { map: self.map }
+    }
+}
+
+// reads a single line of a rebind file, returning None for the lines
+// that have nothing in them
+pub(crate) fn parse_statement(
+    line: &str,
+    line_number: usize,
+) -> Result<Option<Statement<'_>>, KeyMapParseError> {
+    // if the line starts with a cooment, ignore it
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    // only text can have characters that are not ascii in it
+    let splits = split_arguments(line, line_number)?;
+    if splits.iter().any(|split| !split.starts_with('"') && !split.is_ascii()) {
+        return Err(KeyMapParseError::NotAscii { line_number });
+    }
+
+    if splits[0] == "define" || splits[0] == "let" {
+        if splits.len() < 4 {
+            return Err(KeyMapParseError::TooFewArguments { line_number });
+        } else if splits.len() > 4 {
+            return Err(KeyMapParseError::TooManyArguments { line_number });
+        }
+
+        if splits[2] != "=" {
+            return Err(KeyMapParseError::InvalidDefinition { line_number });
+        }
+
+        return Ok(Some(Statement::Define {
+            kind: splits[0],
+            name: splits[1],
+            body: splits[3],
+        }));
+    }
+
+    if splits.len() > 1 && splits[1] == "paste" {
+        return parse_paste(splits[0], &splits[2..], line_number).map(Some);
+    }
+
+    if splits.len() == 3 && splits[1] == "exec" {
+        return Ok(Some(Statement::Exec {
+            from: splits[0],
+            command: splits[2],
+        }));
+    }
+
+    if splits.len() < 2 {
+        return Err(KeyMapParseError::TooFewArguments { line_number });
+    }
+    else if splits.len() > 2 {
+        return Err(KeyMapParseError::TooManyArguments { line_number });
+    }
+
+    Ok(Some(Statement::Bind {
+        from: splits[0],
+        to: splits[1],
+    }))
+}
+
+// parses a single key in the rebind syntax, such as `Ctrl+Alt+45`. There
+// are no aliases outside of a file, so only modifiers and codes are allowed.
+// The key is not on a line of any file, so its errors have line 0, the
+// same as the entries of a json config
+impl FromStr for Key {
+    type Err = KeyMapParseError;
+
+    fn from_str(text: &str) -> Result<Key, KeyMapParseError> {
+        parse_key_at(text, 0)
+    }
+}
+
+// a single key that was read from a line of a config file
+pub(crate) fn parse_key_at(text: &str, line_number: usize) -> Result<Key, KeyMapParseError> {
+    if !text.is_ascii() {
+        return Err(KeyMapParseError::NotAscii { line_number });
+    }
+
+    parse_split(text, &HashMap::new(), line_number)
+}
+
+impl Key {
+    pub fn is_wildcard(self) -> bool {
+        self.state & WILDCARDS != 0
+    }
+
+    pub fn is_button(self) -> bool {
+        self.state & MOUSE_BUTTON != 0
+    }
+
+    // the state as it is sent to and grabbed from the server
+    pub fn modifiers(self) -> u32 {
+        self.state & CORE_STATE
+    }
+
+    // the modifiers that have to be held for the key to match, which
+    // for any key but a wildcard is the whole state
+    pub fn required_modifiers(self) -> u32 {
+        self.state & !WILDCARDS
+    }
+
+    // the key with the lock modifiers taken off, for keys that should
+    // work whether CapsLock or NumLock are on or not
+    pub fn without_locks(self) -> Key {
+        Key {
+            code: self.code,
+            state: self.state & !LOCK_MODIFIERS,
+        }
+    }
+
+    // whether a key that was pressed is matched by this one
+    pub fn matches(self, pressed: Key) -> bool {
+        match self.is_wildcard() {
+            true => {
+                let required = self.required_modifiers();
+                pressed.code == self.code && pressed.state & required == required
+            }
+            false => pressed == self,
+        }
+    }
+
+    // the key with its keycode written as the name it has in names, which
+    // has to be defined as an alias for the text to be parsed again
+    pub fn to_named_string(self, names: &BTreeMap<u32, String>) -> String {
+        key_to_string(self, names)
+    }
+
+    // the names of the modifiers in the state, in the order they are
+    // written in a rebind file
+    pub fn modifier_names(self) -> Vec<&'static str> {
+        MODIFIER_NAMES
+            .iter()
+            .filter(|(_, modifier)| self.state & modifier != 0)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&key_to_string(*self, &BTreeMap::new()))
+    }
+}
+
+// text is written in quotes, the same way it is in a rebind file
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_named_string(&BTreeMap::new()))
+    }
+}
+
+impl fmt::Display for Paste {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_named_string(&BTreeMap::new()))
+    }
+}
+
+impl Target {
+    // the target with the keycodes that have a name written as that name
+    pub fn to_named_string(&self, names: &BTreeMap<u32, String>) -> String {
+        match self {
+            Target::Key(key) => key.to_named_string(names),
+            Target::Text(text) => format!("\"{}\"", escape_text(text)),
+            Target::Exec(command) => format!("exec \"{}\"", escape_text(command)),
+            Target::Paste(paste) => paste.to_named_string(names),
+        }
+    }
+}
+
+impl Paste {
+    pub fn to_named_string(&self, names: &BTreeMap<u32, String>) -> String {
+        let mut text = format!("paste {}", self.selection.name());
+        for transform in &self.transforms {
+            match transform {
+                Transform::Trim => text.push_str(" trim"),
+                Transform::Plain => text.push_str(" plain"),
+                Transform::Replace { pattern, replacement } => text.push_str(&format!(
+                    " replace \"{}\" \"{}\"",
+                    escape_text(pattern),
+                    escape_text(replacement)
+                )),
+            }
+        }
+        if let Some(key) = self.key {
+            text.push_str(&format!(" with {}", key.to_named_string(names)));
+        }
+        text
+    }
+}
+
+impl Selection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Selection::Clipboard => "clipboard",
+            Selection::Primary => "primary",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Selection> {
+        match name {
+            "clipboard" => Some(Selection::Clipboard),
+            "primary" => Some(Selection::Primary),
+            _ => None,
+        }
+    }
+}
+
+impl Paste {
+    // runs the transforms over the text of the selection. The parser
+    // rejects patterns that are not valid, a Paste that was built in
+    // code with one skips it
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for transform in &self.transforms {
+            text = match transform {
+                Transform::Trim => text.trim().to_string(),
+                Transform::Plain => plain_text(&text),
+                Transform::Replace { pattern, replacement } => match Regex::new(pattern) {
+                    Ok(regex) => regex.replace_all(&text, replacement.as_str()).into_owned(),
+                    Err(_) => text,
+                },
+            };
+        }
+        text
+    }
+}
+
+// text copied from a web page or a document keeps some of its formatting
+// even as UTF8_STRING: windows line endings, non-breaking and other odd
+// spaces, zero width characters and control characters
+fn plain_text(text: &str) -> String {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    text.chars()
+        .filter_map(|c| match c {
+            '\n' | '\t' => Some(c),
+            '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}' | '\u{ad}' => None,
+            c if c.is_whitespace() => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
 impl KeyMap {
-    pub fn from_stream(mut stream: impl BufRead) -> Result<KeyMap, KeyMapParseError> {
-        let mut map = HashMap::new();
+    pub fn new() -> KeyMap {
+        KeyMap::default()
+    }
+
+    // binds from to to, replacing whatever from was bound to before
+    pub fn bind(&mut self, from: Key, to: Key) -> &mut KeyMap {
+        self.map.insert(from, Target::Key(to));
+        self
+    }
+
+    pub fn bind_text(&mut self, from: Key, text: &str) -> &mut KeyMap {
+        self.map.insert(from, Target::Text(text.to_string()));
+        self
+    }
+
+    pub fn bind_exec(&mut self, from: Key, command: &str) -> &mut KeyMap {
+        self.map.insert(from, Target::Exec(command.to_string()));
+        self
+    }
+
+    pub fn bind_paste(&mut self, from: Key, paste: Paste) -> &mut KeyMap {
+        self.map.insert(from, Target::Paste(paste));
+        self
+    }
+
+    // returns what from was bound to, if it was bound
+    pub fn unbind(&mut self, from: Key) -> Option<Target> {
+        self.map.remove(&from)
+    }
+
+    // adds all of the bindings of other, which win over the bindings
+    // that are already in this map
+    pub fn merge(&mut self, other: &KeyMap) -> &mut KeyMap {
+        self.map.extend(other.iter().map(|(from, to)| (from, to.clone())));
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Key, &Target)> + '_ {
+        self.map.iter().map(|(&from, to)| (from, to))
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    // fails with the first line that could not be parsed
+    pub fn from_stream(stream: impl BufRead) -> Result<KeyMap, KeyMapParseError> {
+        let checked = KeyMap::check_stream(stream)?;
+        match checked.errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(checked.key_map),
+        }
+    }
+
+    // parses every line, even after one of them failed. Only failing to
+    // read the stream is returned as an error
+    pub fn check_stream(mut stream: impl BufRead) -> Result<CheckedKeyMap, KeyMapParseError> {
+        let mut parser = Parser::default();
+        let mut errors = Vec::new();
 
         let mut amt_read = 1;
         let mut buffer = String::new();
@@ -40,86 +681,532 @@ impl KeyMap {
             buffer.clear();
 
             amt_read = stream.read_line(&mut buffer)?;
-            // println!("buffer: {}", &buffer);
             line_number += 1;
-            
-            if buffer.is_ascii() == false {
-                return Err(KeyMapParseError::NotAscii { line_number });
+
+            let applied = parse_statement(&buffer, line_number).and_then(|statement| match statement {
+                Some(statement) => parser.apply(&statement, line_number),
+                None => Ok(()),
+            });
+            if let Err(error) = applied {
+                errors.push(error);
             }
+        }
 
-            let splits: Vec<&str> = buffer.trim().split(' ').collect();
+        let mut bindings: Vec<(usize, Key, Target)> = parser
+            .map
+            .iter()
+            .map(|(&from, to)| (parser.lines[&from], from, to.clone()))
+            .collect();
+        bindings.sort_by_key(|&(line_number, from, _)| (line_number, from.code, from.state));
 
-            // if the line starts with a cooment, ignore it
-            if splits[0].len() == 0 || &splits[0][0..1] == "#" {
-                continue;
-            }
+        Ok(CheckedKeyMap {
+            warnings: std::mem::take(&mut parser.warnings),
+            key_map: parser.finish(),
+            bindings,
+            errors,
+        })
+    }
 
-            if splits.len() < 2 {
-                return Err(KeyMapParseError::TooFewArguments { line_number });
-            }
-            else if splits.len() > 2 {
-                return Err(KeyMapParseError::TooManyArguments { line_number });
-            }
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.map.keys()
+    }
 
-            let press_key = parse_split(splits[0], line_number)?;
-            let map_key = parse_split(splits[1], line_number)?;
-            map.insert(press_key, map_key);
+    // an exact binding wins over the wildcards, and among the wildcards
+    // the one with the most modifiers wins. `Any+` wins over `*+` when
+    // both have the same modifiers, and wildcards that still tie, such as
+    // `*+Ctrl+43` and `*+Shift+43` for Ctrl+Shift+43, are decided by the
+    // lowest state, so the same key always picks the same binding
+    pub fn target(&self, key: Key) -> Option<Target> {
+        if let Some(to) = self.map.get(&key) {
+            return Some(to.clone());
+        }
+
+        let (from, to) = self
+            .map
+            .iter()
+            .filter(|(from, _)| from.is_wildcard() && from.matches(key))
+            .min_by_key(|(from, _)| {
+                (Reverse(from.required_modifiers().count_ones()), from.state & KEEP_MODIFIERS, from.state)
+            })?;
+
+        // the modifiers can only be added to a key
+        match to {
+            Target::Key(to) if from.state & KEEP_MODIFIERS != 0 => Some(Target::Key(Key {
+                code: to.code,
+                state: to.state | (key.state & !from.required_modifiers()),
+            })),
+            to => Some(to.clone()),
+        }
+    }
 
+    // the key a key is bound to, None for keys that are not bound or
+    // that type text or run a command
+    pub fn mapped_key(&self, key: Key) -> Option<Key> {
+        match self.target(key)? {
+            Target::Key(to) => Some(to),
+            Target::Text(_) | Target::Exec(_) | Target::Paste(_) => None,
         }
+    }
 
-        println!("map is: {:?}", map);
-        Ok(KeyMap { map })
+    // every binding, sorted so that the same map always comes out the same way
+    pub fn bindings(&self) -> Vec<(Key, &Target)> {
+        let mut bindings: Vec<(Key, &Target)> = self.iter().collect();
+        bindings.sort_by_key(|(from, _)| (from.code, from.state));
+        bindings
     }
 
-    pub fn keys(&self) -> impl Iterator<Item = &Key> {
-        self.map.keys()
+    // every binding as the text of both of its sides
+    pub fn bindings_text(&self) -> Vec<(String, String)> {
+        self.bindings()
+            .into_iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
     }
 
-    pub fn mapped_key(&self, key: Key) -> Option<Key> {
-        self.map.get(&key).copied()
+    // writes the map as a rebind file, one binding per line. Aliases and
+    // lists are already expanded, so they are not part of the output
+    pub fn to_rebind(&self) -> String {
+        self.to_rebind_named(&BTreeMap::new())
+    }
+
+    // the same, with every keycode that has a name defined as an alias
+    // in front of the bindings and written as that name
+    pub fn to_rebind_named(&self, names: &BTreeMap<u32, String>) -> String {
+        let mut text = String::new();
+        for (code, name) in names {
+            text.push_str(&format!("define {} = {}\n", name, code));
+        }
+
+        for (from, to) in self.bindings() {
+            text.push_str(&from.to_named_string(names));
+            text.push(' ');
+            text.push_str(&to.to_named_string(names));
+            text.push('\n');
+        }
+
+        text
+    }
+
+    // names for the keycodes the map uses, such as the names of their
+    // keysyms. A name that can not be an alias, or that more than one
+    // keycode has, is left out and those keycodes stay numbers
+    pub fn code_names(&self, name_of: impl Fn(u32) -> Option<String>) -> BTreeMap<u32, String> {
+        let mut codes = Vec::new();
+        for (from, to) in self.bindings() {
+            codes.push(from);
+            match to {
+                Target::Key(to) => codes.push(*to),
+                Target::Paste(Paste { key: Some(key), .. }) => codes.push(*key),
+                _ => (),
+            }
+        }
+        codes.retain(|key| !key.is_button());
+        codes.sort_by_key(|key| key.code);
+        codes.dedup_by_key(|key| key.code);
+
+        let named: Vec<(u32, String)> = codes
+            .into_iter()
+            .filter_map(|key| Some((key.code, name_of(key.code)?)))
+            .filter(|(_, name)| is_alias_name(name) && parse_key(name).is_none() && !KEYWORDS.contains(&name.as_str()))
+            .collect();
+
+        named
+            .iter()
+            .filter(|(code, name)| named.iter().all(|(other, other_name)| other == code || other_name != name))
+            .cloned()
+            .collect()
     }
 }
 
 enum KeyConstant {
     NormalKey { code: u32 },
+    Button { code: u32 },
     ModifierKey { state: u32 },
 }
 
-fn parse_split(split: &str, line_number: usize) -> Result<Key, KeyMapParseError>  {
+// splits a line on spaces, except for the spaces inside of a [...] list
+// which belong to the argument the list is part of
+fn split_arguments(line: &str, line_number: usize) -> Result<Vec<&str>, KeyMapParseError> {
+    let mut splits = Vec::new();
+    let mut start = 0;
+    let mut in_list = false;
+    let mut in_text = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        // text can have spaces and brackets in it, and a quote that
+        // does not end it is escaped
+        if in_text {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_text = false,
+                _ => (),
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_text = true,
+            '[' => in_list = true,
+            ']' => in_list = false,
+            ' ' if !in_list => {
+                splits.push(&line[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    if in_list {
+        return Err(KeyMapParseError::UnclosedList { line_number });
+    }
+    if in_text {
+        return Err(KeyMapParseError::UnclosedText { line_number });
+    }
+
+    splits.push(&line[start..]);
+    Ok(splits)
+}
+
+// expands `Alt+[30 31 32]` into one key per item in the list, with the
+// modifiers in front of the list added to every item. A split without a
+// list is just a list of one key
+fn parse_list(
+    split: &str,
+    aliases: &HashMap<String, Alias>,
+    line_number: usize,
+) -> Result<Vec<Key>, KeyMapParseError> {
+    let (prefix, items) = match split.split_once('[') {
+        Some(parts) => parts,
+        None => return Ok(vec![parse_split(split, aliases, line_number)?]),
+    };
+
+    // the list has to be the last part of the key, as nothing can
+    // come after a normal key
+    let items = match items.strip_suffix(']') {
+        Some(items) if !items.contains(['[', ']']) => items,
+        _ => return Err(KeyMapParseError::NotValidKey { line_number }),
+    };
+
+    let state = match prefix.strip_suffix('+') {
+        Some(modifiers) => {
+            let modifiers = parse_alias_body(modifiers, aliases, line_number)?;
+            if modifiers.code.is_some() {
+                return Err(KeyMapParseError::NotModifier { line_number });
+            }
+            modifiers.state
+        }
+        None if prefix.is_empty() => 0,
+        None => return Err(KeyMapParseError::NotValidKey { line_number }),
+    };
+
+    let mut keys = Vec::new();
+    for item in items.split(' ').filter(|item| !item.is_empty()) {
+        let key = parse_split(item, aliases, line_number)?;
+        keys.push(Key {
+            code: key.code,
+            state: key.state | state,
+        });
+    }
+
+    if keys.is_empty() {
+        return Err(KeyMapParseError::NoKeyPresent { line_number });
+    }
+
+    Ok(keys)
+}
+
+// aliases are expanded where they are used, so any error that comes out
+// of the expansion reports the line of the binding and not of the definition
+fn parse_split(
+    split: &str,
+    aliases: &HashMap<String, Alias>,
+    line_number: usize,
+) -> Result<Key, KeyMapParseError> {
+    let alias = parse_alias_body(split, aliases, line_number)?;
+    match alias.code {
+        Some(code) => Ok(Key {
+            code,
+            state: alias.state,
+        }),
+        None => Err(KeyMapParseError::NoKeyPresent { line_number }),
+    }
+}
+
+// parses everything up to and including the first normal key, the same
+// way a key is parsed, but allows there to be no normal key at all
+fn parse_alias_body(
+    split: &str,
+    aliases: &HashMap<String, Alias>,
+    line_number: usize,
+) -> Result<Alias, KeyMapParseError> {
     let keys = split.split('+');
     let mut state = 0;
 
     for key in keys {
-        let parsed_key = parse_key(key);
-        match parsed_key {
-            Some(parsed_key) => match parsed_key {
-                KeyConstant::NormalKey{ code } => {
-                    return Ok(Key { code, state })
-                },
-                KeyConstant::ModifierKey { state: modifier } => {
-                    state |= modifier;
-                }
-            },
+        if let Some(alias) = aliases.get(key) {
+            state |= alias.state;
+            if let Some(code) = alias.code {
+                return Ok(Alias {
+                    state,
+                    code: Some(code),
+                });
+            }
+            continue;
+        }
+
+        match parse_key(key) {
+            Some(KeyConstant::NormalKey { code }) => {
+                return Ok(Alias {
+                    state,
+                    code: Some(code),
+                })
+            }
+            Some(KeyConstant::Button { code }) => {
+                return Ok(Alias {
+                    state: state | MOUSE_BUTTON,
+                    code: Some(code),
+                })
+            }
+            Some(KeyConstant::ModifierKey { state: modifier }) => {
+                state |= modifier;
+            }
+            None if is_alias_name(key) => {
+                return Err(KeyMapParseError::UnknownAlias {
+                    line_number,
+                    name: key.to_string(),
+                });
+            }
             None => {
                 return Err(KeyMapParseError::NotValidKey { line_number });
             }
         }
     }
 
-    Err(KeyMapParseError::NoKeyPresent { line_number })
+    Ok(Alias { state, code: None })
+}
+
+// `define name = Ctrl+43` names a whole key, while `let name = Ctrl+Alt`
+// names a group of modifiers that still needs a key after it
+fn parse_definition(
+    kind: &str,
+    name: &str,
+    body: &str,
+    aliases: &HashMap<String, Alias>,
+    line_number: usize,
+) -> Result<Alias, KeyMapParseError> {
+    if !is_alias_name(name) {
+        return Err(KeyMapParseError::InvalidDefinition { line_number });
+    }
+
+    // an alias that shadows a modifier or another alias would silently
+    // change the meaning of the lines that come after it, and one named
+    // after a keyword would turn those lines into a different statement
+    if parse_key(name).is_some() || aliases.contains_key(name) || KEYWORDS.contains(&name) {
+        return Err(KeyMapParseError::ReservedName { line_number });
+    }
+
+    let alias = parse_alias_body(body, aliases, line_number)?;
+    match (kind, alias.code) {
+        ("define", None) => Err(KeyMapParseError::NoKeyPresent { line_number }),
+        ("let", Some(_)) => Err(KeyMapParseError::NotModifier { line_number }),
+        ("define", Some(_)) | ("let", None) => Ok(alias),
+        _ => Err(KeyMapParseError::InvalidDefinition { line_number }),
+    }
 }
 
+fn is_alias_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// the names of the bits of a key state, in the order they are written.
+// Every bit of the core protocol has a name, so any state can be written
+// back out. Mod4 is also accepted as another name for Super
+const MODIFIER_NAMES: [(&str, u32); 13] = [
+    ("Shift", 0x1),
+    ("Lock", 0x2),
+    ("Ctrl", 0x4),
+    ("Alt", 0x8),
+    ("Mod2", 0x10),
+    ("Mod3", 0x20),
+    ("Super", 0x40),
+    ("Mod5", 0x80),
+    ("Button1", 0x100),
+    ("Button2", 0x200),
+    ("Button3", 0x400),
+    ("Button4", 0x800),
+    ("Button5", 0x1000),
+];
+
 fn parse_key(current_string: &str) -> Option<KeyConstant> {
     if let Ok(key_code) = current_string.parse::<u32>() {
         return Some(KeyConstant::NormalKey { code: key_code });
     }
 
-    let modifier = match current_string {
-        "Shift" => 0x1,
-        "Ctrl" => 0x4,
-        "Alt" => 0x8,
-        _ => return None,
+    match current_string {
+        "Any" => return Some(KeyConstant::ModifierKey { state: ANY_MODIFIERS }),
+        "*" => return Some(KeyConstant::ModifierKey { state: KEEP_MODIFIERS }),
+        _ => (),
+    }
+
+    // buttons are numbered from 1, and the protocol sends them in a byte
+    if let Some(button) = current_string.strip_prefix("Mouse") {
+        return match button.parse::<u32>() {
+            Ok(code @ 1..=255) => Some(KeyConstant::Button { code }),
+            _ => None,
+        };
+    }
+    if let Some((_, code)) = SCROLL_NAMES.iter().find(|(name, _)| *name == current_string) {
+        return Some(KeyConstant::Button { code: *code });
+    }
+
+    if current_string == "Mod4" {
+        return Some(KeyConstant::ModifierKey { state: 0x40 });
+    }
+
+    let (_, modifier) = MODIFIER_NAMES
+        .iter()
+        .find(|(name, _)| *name == current_string)?;
+
+    Some(KeyConstant::ModifierKey { state: *modifier })
+}
+
+// writes a key the same way it would be written in a rebind file, with the
+// modifiers always in the same order so the output is canonical
+fn key_to_string(key: Key, names: &BTreeMap<u32, String>) -> String {
+    let mut text = String::new();
+    if key.state & ANY_MODIFIERS != 0 {
+        text.push_str("Any+");
+    }
+    if key.state & KEEP_MODIFIERS != 0 {
+        text.push_str("*+");
+    }
+
+    for (name, modifier) in MODIFIER_NAMES {
+        if key.state & modifier != 0 {
+            text.push_str(name);
+            text.push('+');
+        }
+    }
+
+    if !key.is_button() {
+        match names.get(&key.code) {
+            Some(name) => text.push_str(name),
+            None => text.push_str(&key.code.to_string()),
+        }
+        return text;
+    }
+
+    match SCROLL_NAMES.iter().find(|(_, code)| *code == key.code) {
+        Some((name, _)) => text.push_str(name),
+        None => text.push_str(&format!("Mouse{}", key.code)),
+    }
+    text
+}
+
+// reads a quoted argument such as `"a \"b\"\n"`, returning None for any
+// argument that is not quoted
+pub(crate) fn parse_text(split: &str, line_number: usize) -> Result<Option<String>, KeyMapParseError> {
+    let quoted = match split.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => return Ok(None),
     };
 
-    Some(KeyConstant::ModifierKey { state: modifier })
+    let mut text = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(c @ ('\\' | '"')) => text.push(c),
+                _ => return Err(KeyMapParseError::InvalidEscape { line_number }),
+            },
+            // nothing can come after the closing quote
+            '"' if chars.as_str().is_empty() => return Ok(Some(text)),
+            '"' => return Err(KeyMapParseError::CouldNotParse { line_number }),
+            c => text.push(c),
+        }
+    }
+
+    Err(KeyMapParseError::UnclosedText { line_number })
+}
+
+// the arguments after `paste`, the selection comes first and the key
+// that pastes it last
+fn parse_paste<'a>(
+    from: &'a str,
+    arguments: &[&'a str],
+    line_number: usize,
+) -> Result<Statement<'a>, KeyMapParseError> {
+    let (selection, mut arguments) = match arguments.split_first() {
+        Some((selection, arguments)) => (*selection, arguments),
+        None => return Err(KeyMapParseError::TooFewArguments { line_number }),
+    };
+    let selection = Selection::from_name(selection).ok_or(KeyMapParseError::UnknownSelection { line_number })?;
+
+    let mut transforms = Vec::new();
+    let mut with = None;
+    while let Some((&argument, rest)) = arguments.split_first() {
+        arguments = rest;
+        match argument {
+            _ if with.is_some() => return Err(KeyMapParseError::TooManyArguments { line_number }),
+            "trim" => transforms.push(Transform::Trim),
+            "plain" => transforms.push(Transform::Plain),
+            "replace" => {
+                let [pattern, replacement, rest @ ..] = arguments else {
+                    return Err(KeyMapParseError::TooFewArguments { line_number });
+                };
+                transforms.push(Transform::Replace {
+                    pattern: parse_command(pattern, line_number)?,
+                    replacement: parse_command(replacement, line_number)?,
+                });
+                arguments = rest;
+            }
+            "with" => {
+                let [key, rest @ ..] = arguments else {
+                    return Err(KeyMapParseError::TooFewArguments { line_number });
+                };
+                with = Some(*key);
+                arguments = rest;
+            }
+            _ => return Err(KeyMapParseError::UnknownTransform { line_number }),
+        }
+    }
+
+    Ok(Statement::Paste {
+        from,
+        selection,
+        transforms,
+        with,
+    })
+}
+
+pub(crate) fn parse_command(split: &str, line_number: usize) -> Result<String, KeyMapParseError> {
+    match parse_text(split, line_number)? {
+        Some(command) => Ok(command),
+        None => Ok(split.to_string()),
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
 }
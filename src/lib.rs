@@ -1,51 +1,213 @@
 #![allow(unused)]
 
 pub mod child_process;
+pub mod config;
+pub mod control;
+pub mod error;
+pub mod focus;
+pub mod inspect;
 pub mod key_map;
+pub mod reactor;
 pub mod rebind;
+pub mod typing;
+pub mod window_table;
+pub mod window_type;
 mod xbridge;
 
+pub use error::Error;
+pub use xbridge::{WindowHandle, XError};
+
+use config::{Config, ConfigError, WindowFilter};
 use key_map::KeyMap;
 use std::fs::File;
 use std::io::BufReader;
 
-use rebind::WindowInfo;
+use rebind::{KeyMapSource, Options, PauseKeys, WindowInfo};
+use std::path::PathBuf;
 
-pub fn parse_args<'a>(args: &'a Vec<String>) -> (impl Fn(&WindowInfo) -> bool + 'a, KeyMap) {
-    let mut args: Vec<String> = std::env::args().collect();
-    let file = BufReader::new(File::open(&args[1]).unwrap());
-    let key_map = KeyMap::from_stream(file).unwrap();
+pub fn parse_args(args: &[String]) -> Result<Config, Error> {
+    load_config(&args[1..])
+}
 
-    let pid = if args.len() < 4 {
-        None
-    } else {
-        Some(args[3].parse::<u32>().unwrap())
+// loads `<file> [class] [pid]`. The class and pid only apply to rebind
+// files, the structured formats have their own filters in every section
+pub fn load_config(args: &[String]) -> Result<Config, Error> {
+    let path = match args.first() {
+        Some(path) => path,
+        None => return Err(ConfigError::IoError(std::io::ErrorKind::NotFound.into()).into()),
+    };
+    if path.ends_with(".toml") || path.ends_with(".json") {
+        return Ok(Config::from_path(path)?);
+    }
+
+    let file = BufReader::new(File::open(path).map_err(ConfigError::IoError)?);
+    let key_map = KeyMap::from_stream(file)?;
+    Ok(Config::single(parse_filter(&args[1..])?, key_map))
+}
+
+fn parse_filter(args: &[String]) -> Result<WindowFilter, ConfigError> {
+    let pid = match args.get(1) {
+        Some(pid) => Some(pid.parse::<u32>().map_err(|_| ConfigError::InvalidPid { pid: pid.clone() })?),
+        None => None,
     };
 
-    let filter = move |win_info: &WindowInfo| {
-        // turn our Option<&String> to a Option<&str>
-        let class = args.get(2).map(|c| c.as_str());
+    Ok(WindowFilter {
+        class: args.first().cloned(),
+        pid,
+    })
+}
+
+// the config along with the arguments it was loaded from, so it can be
+// loaded again when the file changes
+struct ConfigFile {
+    args: Vec<String>,
+    config: Config,
+}
+
+impl KeyMapSource for ConfigFile {
+    fn key_map_for(&self, win_info: &WindowInfo) -> Option<KeyMap> {
+        self.config.key_map_for(win_info)
+    }
+
+    fn reload(&mut self) -> Result<(), Error> {
+        self.config = parse_args(&self.args)?;
+        Ok(())
+    }
+
+    fn pause_keys(&self) -> PauseKeys {
+        self.config.pause
+    }
+
+    fn section_name(&self, win_info: &WindowInfo) -> Option<String> {
+        self.config.section_label_for(win_info)
+    }
+
+    fn layer(&self, name: &str) -> Option<KeyMap> {
+        self.config.section_named(name).map(|section| section.key_map.clone())
+    }
 
-        println!("class is: {:?} {:?}", win_info.class, class);
+    fn watched_files(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(&self.args[1])]
+    }
+}
 
-        let matches_class = matches_filter(class, win_info.class);
-        let matches_pid = matches_filter(pid, win_info.pid);
-        matches_class && matches_pid
+// removes every `<option> <value>` from the arguments, returning the values
+fn take_option(args: &[String], option: &str) -> (Vec<String>, Vec<String>) {
+    let mut values = Vec::new();
+    let mut rest = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == option {
+            values.extend(args.next().cloned());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+
+    (values, rest)
+}
+
+fn socket_path(sockets: Vec<String>) -> PathBuf {
+    match sockets.last() {
+        Some(socket) => PathBuf::from(socket),
+        None => control::default_socket_path(),
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (displays, args) = take_option(args, "--display");
+    let (sockets, args) = take_option(&args, "--socket");
+    let trace = args.iter().any(|arg| arg == "--trace");
+    let args: Vec<String> = args.into_iter().filter(|arg| arg != "--trace").collect();
+    let config = parse_args(&args)?;
+    let source = ConfigFile { args, config };
+
+    let options = Options {
+        displays,
+        control_socket: Some(socket_path(sockets)),
+        trace,
+        ..Options::default()
+    };
+    rebind::rebind_with(options, source)
+}
+
+// shows the keys that are pressed in a window, `keys [<file> [class] [pid]]`
+pub fn keys(args: &[String]) -> Result<(), Error> {
+    let config = match args.is_empty() {
+        true => None,
+        false => Some(load_config(args)?),
     };
 
-    (filter, key_map)
+    inspect::keys(config.as_ref())
 }
 
-fn matches_filter<P: PartialEq>(filter: Option<P>, value: Option<P>) -> bool {
-    if let Some(filter_val) = filter {
-        match value {
-            Some(value) => value == filter_val,
-            None => false
+// shows what the filter sees of every window, `list-windows [<file> [class] [pid]]`
+pub fn list_windows(args: &[String]) -> Result<(), Error> {
+    let config = match args.is_empty() {
+        true => None,
+        false => Some(load_config(args)?),
+    };
+
+    inspect::list_windows(config.as_ref())
+}
+
+// checks a config file without running it, `check <file> [--strict]`.
+// Returns whether the file passed
+pub fn check(args: &[String]) -> Result<bool, Error> {
+    let strict = args.iter().any(|arg| arg == "--strict");
+    let path = match args.iter().find(|&arg| arg != "--strict") {
+        Some(path) => path,
+        None => return Err(ConfigError::IoError(std::io::ErrorKind::NotFound.into()).into()),
+    };
+
+    inspect::check(std::path::Path::new(path), strict)
+}
+
+// `format <file>`
+pub fn format(args: &[String]) -> Result<(), Error> {
+    match args.first() {
+        Some(path) => inspect::format(std::path::Path::new(path)),
+        None => Err(ConfigError::IoError(std::io::ErrorKind::NotFound.into()).into()),
+    }
+}
+
+// sends a command to a running remapper, `ctl [--socket <path>] <command>`.
+// Returns whether the command succeeded
+pub fn ctl(args: &[String]) -> bool {
+    let (sockets, args) = take_option(args, "--socket");
+    let path = socket_path(sockets);
+
+    match control::send(&path, &args.join(" ")) {
+        Ok(reply) => {
+            print!("{}", reply);
+            !reply.starts_with("error: ")
+        }
+        Err(error) => {
+            eprintln!("could not connect to {}: {}", path.display(), error);
+            false
         }
     }
-    else {
-        true
+}
+
+// prints a rebind file as a structured config, `convert <file> [class] [pid] [--json]`
+pub fn convert(args: &[String]) -> Result<(), Error> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args: Vec<String> = args.iter().filter(|&arg| arg != "--json").cloned().collect();
+    let path = match args.first() {
+        Some(path) => path,
+        None => return Err(ConfigError::IoError(std::io::ErrorKind::NotFound.into()).into()),
+    };
+
+    let file = BufReader::new(File::open(path).map_err(ConfigError::IoError)?);
+    let source = config::convert_rebind(file, parse_filter(&args[1..])?)?;
+
+    if json {
+        println!("{}", source.to_json());
+    } else {
+        print!("{}", source.to_toml());
     }
+    Ok(())
 }
 
 /*
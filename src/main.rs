@@ -1,8 +1,37 @@
-use x11_key_remapper::rebind;
+use x11_key_remapper::config;
+use x11_key_remapper::Error;
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let (filter, key_map) = x11_key_remapper::parse_args(&args);
 
-    rebind::rebind(filter, key_map);
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("convert") => exit_on_error(x11_key_remapper::convert(&args[2..])),
+        Some("schema") => print!("{}", config::SCHEMA),
+        Some("format") => exit_on_error(x11_key_remapper::format(&args[2..])),
+        Some("keys") => exit_on_error(x11_key_remapper::keys(&args[2..])),
+        Some("list-windows") => exit_on_error(x11_key_remapper::list_windows(&args[2..])),
+        // 0 when the file is fine, 1 when it has problems and 2 when
+        // it could not be checked at all
+        Some("check") => match x11_key_remapper::check(&args[2..]) {
+            Ok(true) => (),
+            Ok(false) => std::process::exit(1),
+            Err(error) => {
+                eprintln!("error: {:?}", error);
+                std::process::exit(2);
+            }
+        },
+        Some("ctl") => {
+            if !x11_key_remapper::ctl(&args[2..]) {
+                std::process::exit(1);
+            }
+        }
+        _ => exit_on_error(x11_key_remapper::run(&args)),
+    }
+}
+
+fn exit_on_error(result: Result<(), Error>) {
+    if let Err(error) = result {
+        eprintln!("error: {:?}", error);
+        std::process::exit(1);
+    }
 }
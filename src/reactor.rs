@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::{c_int, CString, OsStr};
+use std::io::{self, ErrorKind};
+use std::mem::{self, MaybeUninit};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// the reason wait returned. T is whatever the caller wants to
+// know about a timer when it goes off
+#[derive(Debug)]
+pub enum Wakeup<T> {
+    // one of the X connections is readable, the events still have to be read
+    XEvents,
+    Timer(T),
+    Signal(c_int),
+    FileChanged(PathBuf),
+    Control(UnixStream),
+}
+
+// a directory that is watched for the files in it that were asked for,
+// editors often replace a file instead of writing to it, which a watch
+// on the file itself would not see
+struct DirectoryWatch {
+    directory: PathBuf,
+    files: HashSet<PathBuf>,
+}
+
+// waits on the X connection and everything else that can wake up the
+// event loop at the same time, so none of them has to block the others
+pub struct Reactor<T> {
+    x_fds: Vec<RawFd>,
+    signal_fd: Option<RawFd>,
+    inotify_fd: Option<RawFd>,
+    watches: HashMap<c_int, DirectoryWatch>,
+    control: Option<UnixListener>,
+    timers: Vec<(Instant, T)>,
+    ready: VecDeque<Wakeup<T>>,
+}
+
+impl<T> Reactor<T> {
+    pub fn new(x_fd: RawFd) -> Reactor<T> {
+        Reactor {
+            x_fds: vec![x_fd],
+            signal_fd: None,
+            inotify_fd: None,
+            watches: HashMap::new(),
+            control: None,
+            timers: Vec::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    // another display, any of them being readable is the same wakeup
+    pub fn add_connection(&mut self, x_fd: RawFd) {
+        self.x_fds.push(x_fd);
+    }
+
+    pub fn add_timer(&mut self, after: Duration, timer: T) {
+        self.timers.push((Instant::now() + after, timer));
+    }
+
+    // the signals are blocked and read from a signalfd instead, so they
+    // can not interrupt the X calls. Threads that are spawned after this
+    // keep them blocked too
+    pub fn catch_signals(&mut self, signals: &[c_int]) -> io::Result<()> {
+        unsafe {
+            let mut mask: MaybeUninit<libc::sigset_t> = MaybeUninit::uninit();
+            libc::sigemptyset(mask.as_mut_ptr());
+            for &signal in signals {
+                libc::sigaddset(mask.as_mut_ptr(), signal);
+            }
+
+            let status = libc::pthread_sigmask(libc::SIG_BLOCK, mask.as_ptr(), std::ptr::null_mut());
+            if status != 0 {
+                return Err(io::Error::from_raw_os_error(status));
+            }
+
+            // reusing the fd adds the signals to the ones it already reads
+            let fd = libc::signalfd(
+                self.signal_fd.unwrap_or(-1),
+                mask.as_ptr(),
+                libc::SFD_NONBLOCK | libc::SFD_CLOEXEC,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.signal_fd = Some(fd);
+        }
+
+        Ok(())
+    }
+
+    pub fn watch_file(&mut self, path: &Path) -> io::Result<()> {
+        let path = path.canonicalize()?;
+        let directory = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+        let inotify_fd = match self.inotify_fd {
+            Some(fd) => fd,
+            None => {
+                let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                self.inotify_fd = Some(fd);
+                fd
+            }
+        };
+
+        let directory_name = CString::new(directory.as_os_str().as_bytes())
+            .map_err(|_| io::Error::from(ErrorKind::InvalidInput))?;
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+        let wd = unsafe { libc::inotify_add_watch(inotify_fd, directory_name.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // adding the same directory again returns the same watch
+        self.watches
+            .entry(wd)
+            .or_insert_with(|| DirectoryWatch {
+                directory,
+                files: HashSet::new(),
+            })
+            .files
+            .insert(path);
+
+        Ok(())
+    }
+
+    pub fn listen(&mut self, listener: UnixListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        self.control = Some(listener);
+        Ok(())
+    }
+
+    // waits until something happens, everything that happened at once is
+    // returned one by one by the following calls
+    pub fn wait(&mut self) -> io::Result<Wakeup<T>> {
+        loop {
+            if let Some(wakeup) = self.ready.pop_front() {
+                return Ok(wakeup);
+            }
+
+            if self.fire_timers() {
+                continue;
+            }
+
+            self.poll()?;
+        }
+    }
+
+    fn fire_timers(&mut self) -> bool {
+        let now = Instant::now();
+        let mut fired = false;
+
+        // timers are fired in the order they were due
+        self.timers.sort_by_key(|(at, _)| *at);
+        while self.timers.first().is_some_and(|(at, _)| *at <= now) {
+            let (_, timer) = self.timers.remove(0);
+            self.ready.push_back(Wakeup::Timer(timer));
+            fired = true;
+        }
+
+        fired
+    }
+
+    fn poll(&mut self) -> io::Result<()> {
+        let timeout = match self.timers.iter().map(|(at, _)| *at).min() {
+            // rounded up, so the timer is due once poll returns
+            Some(at) => {
+                let left = at.saturating_duration_since(Instant::now());
+                (left.as_micros().div_ceil(1000)).min(c_int::MAX as u128) as c_int
+            }
+            None => -1,
+        };
+
+        let control_fd = self.control.as_ref().map(|listener| listener.as_raw_fd());
+        let mut fds: Vec<libc::pollfd> = self
+            .x_fds
+            .iter()
+            .chain([self.signal_fd, self.inotify_fd, control_fd].iter().flatten())
+            .map(|&fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+
+        let count = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if count < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                ErrorKind::Interrupted => Ok(()),
+                _ => Err(error),
+            };
+        }
+
+        // all of the displays are read after any wakeup, so
+        // one wakeup is enough for them
+        if fds.iter().any(|pollfd| pollfd.revents != 0 && self.x_fds.contains(&pollfd.fd)) {
+            self.ready.push_back(Wakeup::XEvents);
+        }
+
+        for pollfd in fds.iter().filter(|pollfd| pollfd.revents != 0) {
+            if self.x_fds.contains(&pollfd.fd) {
+                continue;
+            } else if Some(pollfd.fd) == self.signal_fd {
+                self.read_signals(pollfd.fd)?;
+            } else if Some(pollfd.fd) == self.inotify_fd {
+                self.read_file_changes(pollfd.fd)?;
+            } else if Some(pollfd.fd) == control_fd {
+                self.accept_connections()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_signals(&mut self, fd: RawFd) -> io::Result<()> {
+        loop {
+            let mut info: MaybeUninit<libc::signalfd_siginfo> = MaybeUninit::uninit();
+            let size = mem::size_of::<libc::signalfd_siginfo>();
+            let read = unsafe { libc::read(fd, info.as_mut_ptr() as *mut libc::c_void, size) };
+            if read < 0 {
+                return would_block(io::Error::last_os_error());
+            }
+            if read as usize != size {
+                return Ok(());
+            }
+
+            let signal = unsafe { info.assume_init().ssi_signo };
+            self.ready.push_back(Wakeup::Signal(signal as c_int));
+        }
+    }
+
+    fn read_file_changes(&mut self, fd: RawFd) -> io::Result<()> {
+        let mut changed: Vec<PathBuf> = Vec::new();
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let read = unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if read < 0 {
+                would_block(io::Error::last_os_error())?;
+                break;
+            }
+
+            // every event is followed by its name, padded with nul bytes
+            let header = mem::size_of::<libc::inotify_event>();
+            let mut offset = 0;
+            while offset + header <= read as usize {
+                let event = unsafe {
+                    std::ptr::read_unaligned(buffer.as_ptr().add(offset) as *const libc::inotify_event)
+                };
+                let name = &buffer[offset + header..offset + header + event.len as usize];
+                let name = name.split(|&byte| byte == 0).next().unwrap_or(&[]);
+                offset += header + event.len as usize;
+
+                let watch = match self.watches.get(&event.wd) {
+                    Some(watch) => watch,
+                    None => continue,
+                };
+                let path = watch.directory.join(OsStr::from_bytes(name));
+                if watch.files.contains(&path) && !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+
+        // a save is often a few events, the file is only reported once
+        for path in changed {
+            self.ready.push_back(Wakeup::FileChanged(path));
+        }
+
+        Ok(())
+    }
+
+    fn accept_connections(&mut self) -> io::Result<()> {
+        let listener = match &self.control {
+            Some(listener) => listener,
+            None => return Ok(()),
+        };
+
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => self.ready.push_back(Wakeup::Control(stream)),
+                Err(error) => return would_block(error),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Reactor<T> {
+    fn drop(&mut self) {
+        for fd in [self.signal_fd, self.inotify_fd].iter().flatten() {
+            unsafe {
+                libc::close(*fd);
+            }
+        }
+    }
+}
+
+// the fds are non blocking, so running out of things to read ends up here
+fn would_block(error: io::Error) -> io::Result<()> {
+    match error.kind() {
+        ErrorKind::WouldBlock | ErrorKind::Interrupted => Ok(()),
+        _ => Err(error),
+    }
+}
@@ -1,202 +1,1275 @@
-use std::collections::{HashMap, VecDeque};
-use std::ffi::CString;
-use std::process::Command;
-use std::thread;
-use std::time::Duration;
-
-use super::child_process::ChildProcessState;
-use super::key_map::{Key, KeyMap};
+use std::collections::HashMap;
+use std::ffi::{c_int, CString};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::child_process::{ChildProcessState, Commands};
+use super::control::{self, Command};
+use super::key_map::{Key, KeyMap, Paste, Target, LOCK_MODIFIERS};
+use super::window_table::{WindowState, WindowTable};
+use super::window_type::{WindowKind, WindowType};
+use super::error::Error;
+use super::reactor::{Reactor, Wakeup};
 use super::xbridge::{WindowHandle, XBridge, XBridgeEvent};
 
+// how long a parent can go without being exposed, and how long a child
+// has to close after it was asked to
+const PENDING_TIMEOUT: Duration = Duration::from_secs(5);
+const EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// a window looks the keysym of a key up when it reads the event, so a
+// keycode that was borrowed to type text is only given back after that
+const BORROWED_KEYCODE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// how long a window that was pasted into has to ask for the selection
+// before it gets the text it had before back
+const PASTE_GRACE: Duration = Duration::from_secs(1);
+
+// how often the timeouts and the child process are checked
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+const CHILD_INTERVAL: Duration = Duration::from_millis(500);
+
+// SIGINT and SIGTERM give every window back before exiting, SIGHUP
+// loads the key maps again and SIGUSR1 turns tracing on and off
+const CAUGHT_SIGNALS: [c_int; 5] = [
+    libc::SIGINT,
+    libc::SIGTERM,
+    libc::SIGHUP,
+    libc::SIGCHLD,
+    libc::SIGUSR1,
+];
+
+#[derive(Debug)]
+enum Timer {
+    ExpireWindows,
+    CheckChild,
+}
+
+// the windows of one display, every display has its own connection and
+// its own window ids
 struct DesktopState {
     x: XBridge,
-    parent_child_map: HashMap<WindowHandle, WindowState>,
-    parent_needed_queue: VecDeque<WindowHandle>,
+    // every parent has its own key map, as different windows
+    // can match different sections of the config
+    windows: WindowTable,
+    // remapping can be turned off for every window at once, and a layer
+    // replaces the key maps of every window while it is switched to
+    enabled: bool,
+    layer: Option<(String, KeyMap)>,
+    pause: PauseKeys,
+    stats: Stats,
+    // prints how every key press was handled
+    trace: bool,
+    // what every key and button that is held down was sent as, by parent,
+    // code and whether it is a button, so its release is sent the same way
+    // whatever changed in between
+    held: HashMap<(WindowHandle, u32, bool), Key>,
+    // the commands started by exec bindings that have not exited yet
+    commands: Commands,
+    // the paste that is waiting for the text of the selection to arrive
+    pending_paste: Option<PendingPaste>,
+}
+
+struct PendingPaste {
+    child: WindowHandle,
+    paste: Paste,
+    // the time of the key press, the selection is owned with it
+    time: u64,
+    since: Instant,
+}
+
+#[derive(Default)]
+struct Stats {
+    events: u64,
+    keys_pressed: u64,
+    keys_remapped: u64,
+    x_errors: u64,
 }
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum WindowState {
-    Valid(WindowHandle),
-    Exiting(WindowHandle),
+struct Rebinder<S> {
+    desktops: Vec<DesktopState>,
+    source: S,
+    layer_name: Option<String>,
+    started: Instant,
 }
 
 pub struct WindowInfo<'class> {
     pub class: Option<&'class str>,
     pub pid: Option<u32>,
+    pub window_type: Option<WindowType>,
+    pub kind: WindowKind,
+}
+
+impl WindowInfo<'_> {
+    // the environment the command of an exec binding runs with, so it can
+    // tell which window the key was pressed in
+    pub fn command_env(&self, window: WindowHandle, display: String) -> [(&'static str, String); 4] {
+        [
+            ("X11_KEY_REMAPPER_CLASS", self.class.unwrap_or_default().to_string()),
+            ("X11_KEY_REMAPPER_PID", self.pid.map_or(String::new(), |pid| pid.to_string())),
+            ("X11_KEY_REMAPPER_WINDOW", window.to_string()),
+            ("DISPLAY", display),
+        ]
+    }
+}
+
+// what is known about a window, kept so a WindowInfo can borrow from it
+pub(crate) struct WindowQuery {
+    class: Option<CString>,
+    pid: Option<u32>,
+    window_type: Option<WindowType>,
+    transient_for: Option<WindowHandle>,
+    override_redirect: bool,
+}
+
+impl WindowQuery {
+    // reads everything a window is filtered on, anything that shows a
+    // window the way the filter sees it has to go through here
+    pub(crate) fn read(x: &mut XBridge, window: WindowHandle) -> Result<WindowQuery, Error> {
+        Ok(WindowQuery {
+            pid: x.get_window_pid(window)?,
+            class: x.get_window_class(window)?,
+            window_type: x.get_window_type(window)?,
+            transient_for: x.get_transient_for(window)?,
+            override_redirect: x.is_override_redirect(window)?,
+        })
+    }
+
+    pub(crate) fn info(&self) -> WindowInfo<'_> {
+        WindowInfo {
+            class: self.class.as_ref().and_then(|class| class.to_str().ok()),
+            pid: self.pid,
+            window_type: self.window_type,
+            kind: WindowKind::classify(
+                self.window_type,
+                self.transient_for.is_some(),
+                self.override_redirect,
+            ),
+        }
+    }
+}
+
+// decides which windows get remapped, a window is only given a parent
+// when a key map is returned for it. Any closure that does that is a
+// source that never changes
+pub trait KeyMapSource {
+    fn key_map_for(&self, info: &WindowInfo) -> Option<KeyMap>;
+
+    // called when one of the watched files changed, or on SIGHUP. When it
+    // fails the key maps that were loaded before are kept
+    fn reload(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn watched_files(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    // the bindings of a layer, which replace the bindings of every window
+    // while the layer is switched to
+    fn layer(&self, name: &str) -> Option<KeyMap> {
+        None
+    }
+
+    // only used to explain which part of the config a window got its
+    // key map from
+    fn section_name(&self, info: &WindowInfo) -> Option<String> {
+        None
+    }
+
+    fn pause_keys(&self) -> PauseKeys {
+        PauseKeys::default()
+    }
+}
+
+impl<F: Fn(&WindowInfo) -> Option<KeyMap>> KeyMapSource for F {
+    fn key_map_for(&self, info: &WindowInfo) -> Option<KeyMap> {
+        self(info)
+    }
+}
+
+// the keys that turn remapping off and on, they are grabbed on
+// every parent even while remapping is off
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PauseKeys {
+    // only for the window the key is pressed in
+    pub window: Option<Key>,
+    pub global: Option<Key>,
+}
+
+impl PauseKeys {
+    // every key that has to be grabbed, which is each pause key with every
+    // combination of the lock modifiers
+    fn keys(self) -> impl Iterator<Item = Key> {
+        // none, CapsLock, NumLock and both
+        let locks = [0, 0x2, 0x10, LOCK_MODIFIERS];
+        self.window.into_iter().chain(self.global).flat_map(move |key| {
+            locks.into_iter().map(move |lock| Key {
+                code: key.code,
+                state: key.without_locks().state | lock,
+            })
+        })
+    }
+
+    // whether the key pauses the window it is pressed in, whatever the
+    // lock modifiers are
+    pub fn pauses_window(self, pressed: Key) -> bool {
+        PauseKeys::matches(self.window, pressed)
+    }
+
+    pub fn pauses_all(self, pressed: Key) -> bool {
+        PauseKeys::matches(self.global, pressed)
+    }
+
+    fn matches(pause: Option<Key>, pressed: Key) -> bool {
+        pause.is_some_and(|pause| pause.without_locks() == pressed.without_locks())
+    }
 }
 
-pub fn rebind(window_filter: impl Fn(&WindowInfo) -> bool, key_map: KeyMap) {
-    let mut state = DesktopState {
-        x: XBridge::init().unwrap(),
-        parent_child_map: HashMap::new(),
-        parent_needed_queue: VecDeque::new(),
+#[derive(Default)]
+pub struct Options {
+    // the displays to remap, $DISPLAY is used when there are none
+    pub displays: Vec<String>,
+    // the remapper exits once this process has
+    pub child: Option<ChildProcessState>,
+    pub control_socket: Option<PathBuf>,
+    pub trace: bool,
+}
+
+// Only errors that leave nothing to remap with are returned, the loop
+// keeps going otherwise
+pub fn rebind(key_map_for: impl Fn(&WindowInfo) -> Option<KeyMap>) -> Result<(), Error> {
+    rebind_with(Options::default(), key_map_for)
+}
+
+pub fn rebind_with(options: Options, source: impl KeyMapSource) -> Result<(), Error> {
+    let display_names: Vec<Option<&str>> = match options.displays.is_empty() {
+        true => vec![None],
+        false => options.displays.iter().map(|name| Some(name.as_str())).collect(),
+    };
+
+    let mut desktops = Vec::new();
+    for name in display_names {
+        let mut x = XBridge::init(name)?;
+
+        // in a multi head setup without xinerama every screen has
+        // its own root, which all have to be listened on
+        for screen in 0..x.screen_count() {
+            x.listen_for_window_creation(screen);
+        }
+        println!("listening on display: {}", x.display_name());
+
+        desktops.push(DesktopState {
+            x,
+            windows: WindowTable::new(),
+            enabled: true,
+            layer: None,
+            pause: source.pause_keys(),
+            stats: Stats::default(),
+            trace: options.trace,
+            held: HashMap::new(),
+            commands: Commands::default(),
+            pending_paste: None,
+        });
+    }
+
+    let mut reactor = Reactor::new(desktops[0].x.connection_fd());
+    for desktop in &desktops[1..] {
+        reactor.add_connection(desktop.x.connection_fd());
+    }
+    reactor.catch_signals(&CAUGHT_SIGNALS).map_err(Error::EventLoop)?;
+    for path in source.watched_files() {
+        reactor.watch_file(&path).map_err(Error::EventLoop)?;
+    }
+
+    // the remapper keeps working without the socket, it only can not be
+    // controlled while it runs
+    let control_socket = options.control_socket.as_ref().filter(|path| {
+        match bind_control_socket(path).and_then(|listener| reactor.listen(listener)) {
+            Ok(()) => true,
+            Err(error) => {
+                println!("control socket {} not available: {}", path.display(), error);
+                false
+            }
+        }
+    });
+
+    reactor.add_timer(EXPIRE_INTERVAL, Timer::ExpireWindows);
+    if options.child.is_some() {
+        reactor.add_timer(CHILD_INTERVAL, Timer::CheckChild);
+    }
+
+    let mut rebinder = Rebinder {
+        desktops,
+        source,
+        layer_name: None,
+        started: Instant::now(),
     };
+    let result = rebinder.run(&mut reactor, &options);
+    if let Some(path) = control_socket {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+// a socket file that nothing listens on is left over from a remapper
+// that did not exit cleanly, one that is listened on is another remapper
+// a socket that is left over from a remapper that did not exit cleanly is
+// replaced, but only if it is one of the user's own. Anything else at the
+// path is left alone and the bind fails
+fn bind_control_socket(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty() && !dir.exists()) {
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        let owned = metadata.uid() == unsafe { libc::getuid() };
+        if metadata.file_type().is_socket() && owned && UnixStream::connect(path).is_err() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+impl<S: KeyMapSource> Rebinder<S> {
+    fn run(&mut self, reactor: &mut Reactor<Timer>, options: &Options) -> Result<(), Error> {
+        let child_exited = || options.child.as_ref().is_some_and(|child| child.has_exited());
+
+        loop {
+            // xlib reads events while it waits for replies, so there can be
+            // events waiting even though the connection is not readable
+            for desktop in &mut self.desktops {
+                while let Some(event) = desktop.x.next_event() {
+                    desktop.stats.events += 1;
+                    if let Err(error) = desktop.handle_event(event, &self.source) {
+                        desktop.handle_error(error)?;
+                    }
+                }
+
+                // the errors of requests that were not waited for
+                for error in desktop.x.take_async_errors() {
+                    desktop.handle_error(Error::Protocol(error))?;
+                }
+            }
+
+            match reactor.wait().map_err(Error::EventLoop)? {
+                Wakeup::XEvents => (),
+                Wakeup::Timer(Timer::ExpireWindows) => {
+                    for desktop in &mut self.desktops {
+                        desktop.expire_windows()?;
+                    }
+                    reactor.add_timer(EXPIRE_INTERVAL, Timer::ExpireWindows);
+                }
+                Wakeup::Timer(Timer::CheckChild) => {
+                    if child_exited() {
+                        println!("child exited");
+                        return self.release_all();
+                    }
+                    reactor.add_timer(CHILD_INTERVAL, Timer::CheckChild);
+                }
+                // the thread waiting for the child may not have seen it exit
+                // yet, in which case the timer catches it
+                Wakeup::Signal(libc::SIGCHLD) => {
+                    for desktop in &mut self.desktops {
+                        for (pid, status) in desktop.commands.reap() {
+                            println!("command {} exited: {}", pid, status);
+                        }
+                    }
+                    if child_exited() {
+                        println!("child exited");
+                        return self.release_all();
+                    }
+                }
+                Wakeup::Signal(libc::SIGHUP) => self.reload()?,
+                Wakeup::Signal(libc::SIGUSR1) => {
+                    for desktop in &mut self.desktops {
+                        desktop.trace = !desktop.trace;
+                        println!("tracing on {}: {}", desktop.x.display_name(), desktop.trace);
+                    }
+                }
+                Wakeup::Signal(signal) => {
+                    println!("exiting on signal: {}", signal);
+                    return self.release_all();
+                }
+                Wakeup::FileChanged(path) => {
+                    println!("file changed: {}", path.display());
+                    self.reload()?;
+                }
+                Wakeup::Control(stream) => self.handle_control(stream)?,
+            }
+        }
+    }
+
+    // the children are given back, so they are not closed along with
+    // the parents when the connection goes away
+    fn release_all(&mut self) -> Result<(), Error> {
+        for desktop in &mut self.desktops {
+            desktop.release_all()?;
+        }
+
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<(), Error> {
+        if let Err(error) = self.source.reload() {
+            println!("could not reload, keeping the old key maps: {:?}", error);
+            return Ok(());
+        }
+
+        self.update_key_maps()
+    }
+
+    fn update_key_maps(&mut self) -> Result<(), Error> {
+        // the layer may have changed along with the rest of the config
+        let layer = self
+            .layer_name
+            .as_ref()
+            .and_then(|name| Some((name.clone(), self.source.layer(name)?)));
+        for desktop in &mut self.desktops {
+            desktop.layer = layer.clone();
+            desktop.pause = self.source.pause_keys();
+            desktop.update_key_maps(&self.source)?;
+        }
+
+        Ok(())
+    }
+
+    // a client that went away before reading the reply is not an error
+    // for the remapper, only errors from the X server are returned
+    fn handle_control(&mut self, mut stream: UnixStream) -> Result<(), Error> {
+        let line = match control::read_command(&stream) {
+            Ok(line) => line,
+            Err(error) => {
+                println!("could not read control command: {}", error);
+                return Ok(());
+            }
+        };
+        println!("control command: {}", line);
+
+        let reply = match Command::parse(&line) {
+            Ok(command) => self.run_command(command)?,
+            Err(error) => Err(error),
+        };
+
+        let _ = match reply {
+            Ok(reply) => write!(stream, "{}", reply),
+            Err(error) => writeln!(stream, "error: {}", error),
+        };
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: Command) -> Result<Result<String, String>, Error> {
+        match command {
+            Command::List => {
+                let mut reply = String::new();
+                for desktop in &mut self.desktops {
+                    reply += &desktop.list_windows()?;
+                }
+                Ok(Ok(reply))
+            }
+            Command::Enable(window) | Command::Disable(window) => {
+                let enabled = matches!(command, Command::Enable(_));
+                for desktop in &mut self.desktops {
+                    match window {
+                        Some(window) => {
+                            if desktop.set_window_enabled(window, enabled)? {
+                                return Ok(Ok(String::new()));
+                            }
+                        }
+                        None => desktop.set_enabled(enabled)?,
+                    }
+                }
+
+                match window {
+                    Some(window) => Ok(Err(format!("window is not wrapped: 0x{:x}", window))),
+                    None => Ok(Ok(String::new())),
+                }
+            }
+            Command::Layer(name) => {
+                let layer = match &name {
+                    Some(name) => match self.source.layer(name) {
+                        Some(layer) => Some((name.clone(), layer)),
+                        None => return Ok(Err(format!("no layer named {}", name))),
+                    },
+                    None => None,
+                };
+
+                self.layer_name = name;
+                for desktop in &mut self.desktops {
+                    desktop.set_layer(layer.clone())?;
+                }
+                Ok(Ok(String::new()))
+            }
+            // the error goes to the client instead of only being printed
+            Command::Reload => match self.source.reload() {
+                Ok(()) => {
+                    self.update_key_maps()?;
+                    Ok(Ok(String::new()))
+                }
+                Err(error) => Ok(Err(format!("could not reload: {:?}", error))),
+            },
+            Command::Release(window) => {
+                for desktop in &mut self.desktops {
+                    if desktop.release(window)? {
+                        return Ok(Ok(String::new()));
+                    }
+                }
+                Ok(Err(format!("window is not wrapped: 0x{:x}", window)))
+            }
+            Command::Stats => Ok(Ok(self.stats())),
+        }
+    }
+
+    fn stats(&self) -> String {
+        let mut stats = Stats::default();
+        let mut states = [0; 4];
+        for desktop in &self.desktops {
+            stats.events += desktop.stats.events;
+            stats.keys_pressed += desktop.stats.keys_pressed;
+            stats.keys_remapped += desktop.stats.keys_remapped;
+            stats.x_errors += desktop.stats.x_errors;
+
+            for (_, state) in desktop.windows.states() {
+                let index = match state {
+                    WindowState::Pending { .. } => 0,
+                    WindowState::Wrapped(_) => 1,
+                    WindowState::Exiting { .. } => 2,
+                    WindowState::Released(_) => 3,
+                };
+                states[index] += 1;
+            }
+        }
+
+        format!(
+            "uptime {}\ndisplays {}\nlayer {}\nevents {}\nkeys_pressed {}\nkeys_remapped {}\nx_errors {}\n\
+             pending {}\nwrapped {}\nexiting {}\nreleased {}\n",
+            self.started.elapsed().as_secs(),
+            self.desktops.len(),
+            self.layer_name.as_deref().unwrap_or("-"),
+            stats.events,
+            stats.keys_pressed,
+            stats.keys_remapped,
+            stats.x_errors,
+            states[0],
+            states[1],
+            states[2],
+            states[3],
+        )
+    }
+}
+
+impl DesktopState {
+    fn release_all(&mut self) -> Result<(), Error> {
+        let wrapped: Vec<(WindowHandle, WindowHandle)> = self.windows.wrapped().collect();
+        for (parent, child) in wrapped {
+            self.windows.release(parent);
+            if let Err(error) = self.x.release_window(child, parent) {
+                self.handle_error(error)?;
+            }
+        }
+
+        self.x.restore_keycodes(Duration::ZERO)
+    }
+
+    // the windows that are already wrapped are given the key map they would
+    // get now. A window that no longer matches keeps its parent, but none
+    // of its keys are remapped anymore
+    fn update_key_maps(&mut self, source: &impl KeyMapSource) -> Result<(), Error> {
+        let wrapped: Vec<(WindowHandle, WindowHandle)> = self.windows.wrapped().collect();
+        for (parent, child) in wrapped {
+            let result = self.query_window(child).and_then(|query| {
+                let key_map = source.key_map_for(&query.info()).unwrap_or_default();
+                self.windows.set_key_map(parent, key_map);
+                self.grab_active_keys(parent)
+            });
+
+            if let Err(error) = result {
+                self.handle_error(error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the keys that are remapped for a window right now, None when
+    // remapping is turned off for it
+    fn active_key_map(&self, parent: WindowHandle) -> Option<&KeyMap> {
+        if !self.enabled || !self.windows.is_enabled(parent) {
+            return None;
+        }
+
+        let key_map = self.windows.key_map(parent)?;
+        Some(self.layer.as_ref().map_or(key_map, |(_, layer)| layer))
+    }
+
+    // keys that are not grabbed go to the child without passing through
+    // the parent, so turning remapping off ungrabs them. Only the pause
+    // keys stay grabbed, so remapping can be turned on again
+    fn grab_active_keys(&mut self, parent: WindowHandle) -> Result<(), Error> {
+        let active = self.active_key_map(parent).cloned();
+        let paused = active.is_none();
+
+        let mut key_map = active.unwrap_or_default();
+        for key in self.pause.keys() {
+            key_map.bind(key, key);
+        }
+        self.x.grab_keys(parent, key_map)?;
+
+        match self.windows.wrapped_child(parent) {
+            Some(child) => self.x.set_paused(parent, child, paused),
+            None => Ok(()),
+        }
+    }
+
+    fn grab_all_active_keys(&mut self) -> Result<(), Error> {
+        let parents: Vec<WindowHandle> = self.windows.wrapped().map(|(parent, _)| parent).collect();
+        for parent in parents {
+            if let Err(error) = self.grab_active_keys(parent) {
+                self.handle_error(error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.enabled = enabled;
+        self.grab_all_active_keys()
+    }
+
+    // returns false when the window is not wrapped on this display
+    fn set_window_enabled(&mut self, window: WindowHandle, enabled: bool) -> Result<bool, Error> {
+        let parent = match self.windows.find_wrapped(window) {
+            Some(parent) => parent,
+            None => return Ok(false),
+        };
+
+        self.windows.set_enabled(parent, enabled);
+        if let Err(error) = self.grab_active_keys(parent) {
+            self.handle_error(error)?;
+        }
+        Ok(true)
+    }
+
+    fn set_layer(&mut self, layer: Option<(String, KeyMap)>) -> Result<(), Error> {
+        self.layer = layer;
+        self.grab_all_active_keys()
+    }
+
+    fn release(&mut self, window: WindowHandle) -> Result<bool, Error> {
+        let parent = match self.windows.find_wrapped(window) {
+            Some(parent) => parent,
+            None => return Ok(false),
+        };
+
+        let child = self.windows.release(parent).unwrap();
+        if let Err(error) = self.x.release_window(child, parent) {
+            self.handle_error(error)?;
+        }
+        Ok(true)
+    }
+
+    // one line for every wrapped window: display, parent, child, class
+    // and whether its keys are remapped
+    fn list_windows(&mut self) -> Result<String, Error> {
+        let mut wrapped: Vec<(WindowHandle, WindowHandle)> = self.windows.wrapped().collect();
+        wrapped.sort();
+
+        let display = self.x.display_name();
+        let mut list = String::new();
+        for (parent, child) in wrapped {
+            let class = match self.x.get_window_class(child) {
+                Ok(class) => class,
+                Err(error) => {
+                    self.handle_error(error)?;
+                    continue;
+                }
+            };
+            let class = class.as_ref().map_or("-".into(), |class| class.to_string_lossy());
+            let enabled = match self.active_key_map(parent) {
+                Some(_) => "enabled",
+                None => "disabled",
+            };
 
-    let screen = state.x.default_screen();
-    state.x.listen_for_window_creation(screen);
+            list += &format!("{} 0x{:x} 0x{:x} {} {}\n", display, parent, child, class, enabled);
+        }
+
+        Ok(list)
+    }
+
+    fn query_window(&mut self, window: WindowHandle) -> Result<WindowQuery, Error> {
+        WindowQuery::read(&mut self.x, window)
+    }
 
-    loop {
-        let event = state.x.wait_next_event();
+    fn handle_event(&mut self, event: XBridgeEvent, source: &impl KeyMapSource) -> Result<(), Error> {
         match event {
-            XBridgeEvent::Expose { parent } => state.handle_parent_expose(parent, &key_map),
+            XBridgeEvent::Expose { parent } => self.handle_parent_expose(parent)?,
             XBridgeEvent::ConfigureNotify {
                 parent,
                 width,
                 height,
             } => {
-                state.handle_parent_update(parent, width, height);
+                self.handle_parent_update(parent, width, height)?;
             }
             XBridgeEvent::ReparentNotify { window } => {
                 println!("reparent window: {}", window);
 
-                let pid = state.x.get_window_pid(window);
-                let class = state.x.get_window_class(window);
-                let class_str = class.as_ref().map(|c| c.to_str().unwrap());
-                let info = WindowInfo {
-                    pid,
-                    class: class_str,
-                };
+                // our own parents carry the class and pid of their child,
+                // so they would pass the filter and be wrapped again
+                if self.x.is_created_window(window) {
+                    return Ok(());
+                }
 
-                let pass_filter = window_filter(&info);
-                println!("window: {} passed filter: {}", window, pass_filter);
+                let query = self.query_window(window)?;
+                let info = query.info();
 
-                if pass_filter == false {
-                    continue;
+                // menus and tooltips are never wrapped, whatever the filter says
+                if !info.kind.is_wrapped() {
+                    println!("window: {} is not wrapped, kind: {:?}", window, info.kind);
+                    return Ok(());
                 }
 
-                state.handle_window_reparent(window, screen);
+                let key_map = source.key_map_for(&info);
+                println!("window: {} passed filter: {}", window, key_map.is_some());
+
+                let key_map = match key_map {
+                    Some(key_map) => key_map,
+                    None => return Ok(()),
+                };
+
+                let transient_for = match info.kind {
+                    WindowKind::Transient => query.transient_for,
+                    _ => None,
+                };
+                self.handle_window_reparent(window, key_map, transient_for)?;
+            }
+            XBridgeEvent::KeyPress { parent, key, time } => {
+                self.handle_key_press(parent, key, time, source)?;
+            }
+            XBridgeEvent::KeyRelease { window, key } => self.handle_release(window, key)?,
+            XBridgeEvent::Selection(event) => {
+                if let Some(text) = self.x.handle_selection_event(event)? {
+                    self.finish_paste(text)?;
+                }
+            }
+            XBridgeEvent::ButtonPress { parent, button, time } => {
+                self.handle_key_press(parent, button, time, source)?;
             }
-            XBridgeEvent::KeyPress { parent, key } => {
-                state.handle_key_press(parent, key, &key_map);
+            XBridgeEvent::ButtonRelease { parent, button } => {
+                self.handle_release(parent, button)?;
             }
             XBridgeEvent::DestroyRequest { window } => {
                 println!("destroy request window: {}", window);
 
-                let child_state = match state.parent_child_map.get(&window) {
-                    Some(&child) => child,
-                    None => continue,
-                };
-
-                if let WindowState::Valid(child) = child_state {
-                    state.x.notify_child_should_close(child, window);
-                    state
-                        .parent_child_map
-                        .insert(window, WindowState::Exiting(child));
+                if let Some(child) = self.windows.start_exit(window, Instant::now()) {
+                    self.x.notify_child_should_close(child, window)?;
                 }
             }
             XBridgeEvent::DestroyNotify { window } => {
                 println!("destroy notify window: {}", window);
 
-                // get all keys where it is exiting, and the window
-                // is the window that is exiting
-                let mut keys = Vec::new();
-                for (parent, child_window_state) in state.parent_child_map.iter() {
-                    let should_remove = match child_window_state {
-                        WindowState::Valid(_) => false,
-                        WindowState::Exiting(child_window) => window == *child_window
-                    };
-                    if should_remove {
-                        keys.push(*parent);
+                // a child that closed by itself leaves an empty parent behind
+                if let Some((parent, WindowState::Wrapped(child))) =
+                    self.windows.remove_destroyed(window)
+                {
+                    if child == window {
+                        self.x.destroy_window(parent)?;
                     }
                 }
-
-                // clean up all of those values
-                for key in keys {
-                    state.parent_child_map.remove(&key);
+            }
+            XBridgeEvent::ParentFocus { parent } => self.handle_parent_focus(parent, None)?,
+            XBridgeEvent::TakeFocus { parent, time } => {
+                self.handle_parent_focus(parent, Some(time))?;
+            }
+            XBridgeEvent::PropertyChange { window, property } => {
+                if let Some(parent) = self.windows.wrapped_parent(window) {
+                    self.x.sync_child_property(window, parent, property)?;
+                } else if let Some(child) = self.windows.wrapped_child(window) {
+                    self.x.sync_parent_property(window, child, property)?;
                 }
             }
-            XBridgeEvent::ParentFocus { parent } => match state.parent_child_map.get(&parent) {
-                Some(&child_state) => {
-                    if let WindowState::Valid(child) = child_state {
-                        state.x.focus_window(child);
-                    }
+            XBridgeEvent::StateRequest { window, data } => {
+                // the window manager does not manage the child, so it would
+                // never see the request unless it is made for the parent
+                if let Some(parent) = self.windows.wrapped_parent(window) {
+                    self.x.forward_state_request(parent, data)?;
                 }
-                None => (),
-            },
+            }
+            XBridgeEvent::ChildConfigureRequest {
+                parent,
+                child,
+                width,
+                height,
+            } => {
+                self.handle_child_configure_request(parent, child, width, height)?;
+            }
+            XBridgeEvent::ChildMapRequest { child } => self.x.map_window(child)?,
         }
+
+        Ok(())
     }
-}
 
-impl DesktopState {
-    fn handle_key_press(&mut self, parent: WindowHandle, pressed_key: Key, key_map: &KeyMap) {
-        let new_key = match key_map.mapped_key(pressed_key) {
-            Some(new_key) => new_key,
+    // windows of other clients can be destroyed at any point, so most errors
+    // are a window that vanished before we got to it. Whatever was known
+    // about it is forgotten, as no DestroyNotify may be coming for it
+    fn handle_error(&mut self, error: Error) -> Result<(), Error> {
+        let error = match error {
+            Error::Protocol(error) => error,
+            error => return Err(error),
+        };
+        self.stats.x_errors += 1;
+
+        if !error.is_bad_window() {
+            eprintln!("x error: {:?}", error);
+            return Ok(());
+        }
+        if self.trace {
+            println!("trace: x error for a window that is gone: {:?}", error);
+        }
+
+        match self.windows.remove_destroyed(error.resource) {
+            Some((parent, WindowState::Wrapped(child) | WindowState::Pending { child, .. }))
+                if child == error.resource =>
+            {
+                // the parent is ours, so it can only fail if it is gone too
+                let _ = self.x.destroy_window(parent);
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn handle_key_press(
+        &mut self,
+        parent: WindowHandle,
+        pressed_key: Key,
+        time: u64,
+        source: &impl KeyMapSource,
+    ) -> Result<(), Error> {
+        if self.trace {
+            self.trace_key_press(parent, pressed_key, source)?;
+        }
+
+        // the pause keys are never sent on, even while paused
+        if self.pause.pauses_all(pressed_key) {
+            println!("remapping paused: {}", self.enabled);
+            return self.set_enabled(!self.enabled);
+        }
+        if self.pause.pauses_window(pressed_key) {
+            let enabled = self.windows.is_enabled(parent);
+            println!("remapping paused for {}: {}", parent, enabled);
+            self.windows.set_enabled(parent, !enabled);
+            return self.grab_active_keys(parent);
+        }
+
+        let target = self
+            .active_key_map(parent)
+            .and_then(|key_map| key_map.target(pressed_key));
+
+        self.stats.keys_pressed += 1;
+        if target.is_some() {
+            self.stats.keys_remapped += 1;
+        }
+
+        let new_key = match target {
+            Some(Target::Key(new_key)) => new_key,
+            Some(Target::Text(text)) => return self.type_text(parent, &text),
+            Some(Target::Exec(command)) => return self.run_command(parent, &command),
+            Some(Target::Paste(paste)) => return self.start_paste(parent, paste, time),
             None => pressed_key,
         };
 
-        println!(
-            "from {}:{:x} to {}:{:x}",
-            pressed_key.code, pressed_key.state, new_key.code, new_key.state
-        );
+        let child = match self.windows.wrapped_child(parent) {
+            Some(child) => child,
+            None => {
+                if self.trace {
+                    println!("trace:   the parent has no wrapped child, nothing was sent");
+                }
+                return Ok(());
+            }
+        };
+
+        // whatever was pressed is released when the key or button that
+        // sent it is, see handle_release
+        self.held.insert((parent, pressed_key.code, pressed_key.is_button()), new_key);
+        match new_key.is_button() {
+            true => self.x.send_button_event(child, new_key, true)?,
+            false => self.x.send_key_event(child, new_key, true)?,
+        }
+
+        if self.trace {
+            let event = match new_key.is_button() {
+                true => "ButtonPress",
+                false => "KeyPress",
+            };
+            println!(
+                "trace:   pressed {} in 0x{:x} with a synthetic {} from XSendEvent",
+                new_key, child, event
+            );
+        }
+
+        Ok(())
+    }
 
-        let child_state = match self.parent_child_map.get(&parent) {
-            Some(&child_window) => child_window,
-            None => return,
+    // the command is told which window it was run for, a command that can
+    // not be started does not stop the remapper
+    fn run_command(&mut self, parent: WindowHandle, command: &str) -> Result<(), Error> {
+        let child = match self.windows.wrapped_child(parent) {
+            Some(child) => child,
+            None => return Ok(()),
         };
 
-        if let WindowState::Valid(child) = child_state {
-            self.x.send_key_event(child, new_key);
+        let query = self.query_window(child)?;
+        let env = query.info().command_env(child, self.x.display_name());
+
+        match self.commands.spawn(command, &env) {
+            Ok(pid) if self.trace => println!("trace:   ran {} as pid {} for 0x{:x}", command, pid, child),
+            Ok(_) => (),
+            Err(error) => println!("could not run {}: {:?}", command, error),
         }
+        Ok(())
     }
 
-    fn handle_parent_update(&mut self, parent: WindowHandle, width: u32, height: u32) {
-        match self.parent_child_map.get(&parent) {
-            Some(&child_state) => {
-                if let WindowState::Valid(child) = child_state {
-                    self.x.resize_to(child, width, height);
-                }
+    // the selection is read from its owner, the paste finishes when
+    // all of it has arrived
+    fn start_paste(&mut self, parent: WindowHandle, paste: Paste, time: u64) -> Result<(), Error> {
+        let child = match self.windows.wrapped_child(parent) {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+
+        self.x.request_selection(paste.selection, time)?;
+        if self.trace {
+            println!("trace:   reading the {} selection for 0x{:x}", paste.selection.name(), child);
+        }
+        self.pending_paste = Some(PendingPaste {
+            child,
+            paste,
+            time,
+            since: Instant::now(),
+        });
+        Ok(())
+    }
+
+    // the transformed text replaces the selection until it was pasted,
+    // and the child is made to paste it
+    fn finish_paste(&mut self, text: String) -> Result<(), Error> {
+        let PendingPaste { child, paste, time, .. } = match self.pending_paste.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        let transformed = paste.apply(&text);
+        if transformed.is_empty() {
+            println!("nothing to paste from the {} selection", paste.selection.name());
+            return Ok(());
+        }
+        if !self.x.own_selection(paste.selection, transformed, text, time)? {
+            println!("could not own the {} selection", paste.selection.name());
+            return Ok(());
+        }
+
+        let key = match paste.key.or_else(|| self.x.paste_key(paste.selection)) {
+            Some(key) => key,
+            None => {
+                println!("no key pastes the {} selection", paste.selection.name());
+                return Ok(());
+            }
+        };
+
+        match key.is_button() {
+            true => {
+                self.x.send_button_event(child, key, true)?;
+                self.x.send_button_event(child, key, false)?;
+            }
+            false => {
+                self.x.send_key_event(child, key, true)?;
+                self.x.send_key_event(child, key, false)?;
             }
-            None => (),
         }
+        self.x.selection_pasted(paste.selection, child);
+        if self.trace {
+            println!("trace:   pasted the {} selection in 0x{:x} with {}", paste.selection.name(), child, key);
+        }
+        Ok(())
+    }
+
+    fn type_text(&mut self, parent: WindowHandle, text: &str) -> Result<(), Error> {
+        let child = match self.windows.wrapped_child(parent) {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+
+        // the text is typed up to the character that could not be, which
+        // does not stop the remapper
+        match self.x.type_text(child, text) {
+            Err(Error::NoKeycode { character }) => {
+                println!("no keycode left to type {:?}", character);
+                return Ok(());
+            }
+            result => result?,
+        }
+        if self.trace {
+            println!(
+                "trace:   typed {} in 0x{:x} with synthetic key events from XSendEvent",
+                Target::Text(text.to_string()),
+                child
+            );
+        }
+
+        Ok(())
+    }
+
+    // a key or button is released as whatever its press was sent as, so a
+    // key bound to a button holds the button down for as long as the key
+    // is. Nothing was sent for the pause keys or for text, commands and
+    // pastes, so neither is their release
+    fn handle_release(&mut self, parent: WindowHandle, released: Key) -> Result<(), Error> {
+        let sent = match self.held.remove(&(parent, released.code, released.is_button())) {
+            Some(sent) => sent,
+            None => return Ok(()),
+        };
+        let child = match self.windows.wrapped_child(parent) {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+
+        match sent.is_button() {
+            true => self.x.send_button_event(child, sent, false)?,
+            false => self.x.send_key_event(child, sent, false)?,
+        }
+        if self.trace {
+            println!("trace: released {} in 0x{:x} for {}", sent, child, released);
+        }
+
+        Ok(())
     }
 
-    fn handle_parent_expose(&mut self, parent: WindowHandle, key_map: &KeyMap) {
+    // prints everything the handling of a key press depends on, the lines
+    // are read together with the one handle_key_press prints after it
+    fn trace_key_press(
+        &mut self,
+        parent: WindowHandle,
+        key: Key,
+        source: &impl KeyMapSource,
+    ) -> Result<(), Error> {
+        let modifiers = match key.modifier_names() {
+            names if names.is_empty() => "none".to_string(),
+            names => names.join("+"),
+        };
         println!(
-            "parent expose: {}, has child: {}",
+            "trace: {} {} on {} in parent 0x{:x}, code {} state 0x{:x} ({})",
+            if key.is_button() { "button" } else { "key" },
+            key,
+            self.x.display_name(),
             parent,
-            self.parent_child_map.get(&parent).is_some()
+            key.code,
+            key.modifiers(),
+            modifiers
         );
-        match self.parent_child_map.get(&parent) {
-            Some(&child_state) => {
-                if let WindowState::Valid(child) = child_state {
-                    self.x.resize_to_parent(child, parent);
-                }
+
+        match self.windows.wrapped_child(parent) {
+            Some(child) => {
+                let query = self.query_window(child)?;
+                let info = query.info();
+                println!(
+                    "trace:   child 0x{:x}, class {}, pid {}, section {}",
+                    child,
+                    info.class.unwrap_or("-"),
+                    info.pid.map_or("-".to_string(), |pid| pid.to_string()),
+                    source.section_name(&info).as_deref().unwrap_or("-")
+                );
+            }
+            None => println!("trace:   no wrapped child, state {:?}", self.windows.state(parent)),
+        }
+
+        match &self.layer {
+            Some((name, _)) => println!("trace:   layer {} replaces the bindings of every window", name),
+            None => println!("trace:   no layer"),
+        }
+
+        if self.pause.pauses_all(key) {
+            println!("trace:   pause key for every window, remapping turns {}", on_off(!self.enabled));
+            return Ok(());
+        }
+        if self.pause.pauses_window(key) {
+            let enabled = self.windows.is_enabled(parent);
+            println!("trace:   pause key for this window, remapping turns {}", on_off(!enabled));
+            return Ok(());
+        }
+
+        let key_map = match self.active_key_map(parent) {
+            Some(key_map) => key_map,
+            None if !self.enabled => {
+                println!("trace:   not remapped, remapping is paused for every window");
+                return Ok(());
+            }
+            None if !self.windows.is_enabled(parent) => {
+                println!("trace:   not remapped, remapping is paused for this window");
+                return Ok(());
             }
             None => {
-                // if the window is exposed and there is no child in the queue
-                // that means expose must have come from deletion of the window
-                // therefore, it needs to just return
-                let child = match self.parent_needed_queue.pop_front() {
-                    Some(child) => child,
-                    None => return,
-                };
+                println!("trace:   not remapped, the parent has no key map");
+                return Ok(());
+            }
+        };
 
-                self.parent_child_map
-                    .insert(parent, WindowState::Valid(child));
-                self.x.reparent_window(child, parent);
-                println!("child parented: {}", child);
-                self.x.grab_keys(parent, key_map.clone());
+        match key_map.target(key) {
+            Some(to) => println!("trace:   matched {} -> {}", key, to),
+            None => {
+                // the state has to match exactly, so the likely reason is
+                // a modifier such as NumLock being on
+                let mut near: Vec<String> = key_map
+                    .keys()
+                    .filter(|bound| bound.code == key.code)
+                    .map(|bound| bound.to_string())
+                    .collect();
+                near.sort();
+                match near.is_empty() {
+                    true => println!("trace:   no binding for {} in {} bindings", key, key_map.len()),
+                    false => println!(
+                        "trace:   no binding for {}, the code is only bound with other modifiers: {}",
+                        key,
+                        near.join(" ")
+                    ),
+                }
             }
         }
+
+        Ok(())
     }
 
-    fn handle_window_reparent(&mut self, window: WindowHandle, screen: i32) {
-        let child_window = window;
-        let in_queue = self.parent_needed_queue.iter().any(|&w| w == child_window);
-        let already_parented = self.parent_child_map.values().any(|&state| match state {
-            WindowState::Valid(child) => child == window,
-            WindowState::Exiting(child) => child == window,
-        });
+    fn handle_parent_focus(&mut self, parent: WindowHandle, time: Option<u64>) -> Result<(), Error> {
+        self.x.pass_focus(&self.windows, parent, time)?;
+        Ok(())
+    }
 
-        if in_queue || already_parented {
-            println!(
-                "window is in queue: {} already parented: {}",
-                in_queue, already_parented
-            );
-            return;
+    // the child is always the size of its parent, so a child that resizes
+    // itself asks for its parent to be resized instead. The window manager
+    // may refuse that, so the child is told the size it really has
+    fn handle_child_configure_request(
+        &mut self,
+        parent: WindowHandle,
+        child: WindowHandle,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<(), Error> {
+        if self.windows.wrapped_child(parent) != Some(child) {
+            return Ok(());
+        }
+
+        if width.is_some() || height.is_some() {
+            let (current_width, current_height) = self.x.window_size(parent)?;
+            self.x.resize_to(
+                parent,
+                width.unwrap_or(current_width),
+                height.unwrap_or(current_height),
+            )?;
         }
 
-        self.parent_needed_queue.push_back(child_window);
-        self.x.create_window(screen);
+        self.x.confirm_configure(child)
+    }
+
+    fn handle_parent_update(&mut self, parent: WindowHandle, width: u32, height: u32) -> Result<(), Error> {
+        if let Some(child) = self.windows.wrapped_child(parent) {
+            self.x.resize_to(child, width, height)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_parent_expose(&mut self, parent: WindowHandle) -> Result<(), Error> {
+        println!(
+            "parent expose: {}, state: {:?}",
+            parent,
+            self.windows.state(parent)
+        );
+
+        if let Some(child) = self.windows.wrapped_child(parent) {
+            return self.x.resize_to_parent(child, parent);
+        }
+
+        // only the parent that was created for the child is given it, an
+        // expose from any other window is not ours or is already handled
+        let child = match self.windows.wrap(parent) {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+
+        self.x.reparent_window(child, parent)?;
+        println!("child parented: {}", child);
+
+        // the properties may have changed since the parent was
+        // created, so they are copied again once they are watched
+        self.x.watch_properties(child)?;
+        self.x.mirror_properties(child, parent)?;
+
+        self.grab_active_keys(parent)
+    }
+
+    fn handle_window_reparent(
+        &mut self,
+        window: WindowHandle,
+        key_map: KeyMap,
+        transient_for: Option<WindowHandle>,
+    ) -> Result<(), Error> {
+        // the child is pending, wrapped, on its way out, or was given back
+        if self.windows.has_child(window) {
+            println!("window is already known: {}", window);
+            return Ok(());
+        }
+
+        // a dialog is transient for the child the application knows about,
+        // but the window manager only knows about the parent of that child
+        let transient_for =
+            transient_for.map(|owner| self.windows.wrapped_parent(owner).unwrap_or(owner));
+        let parent = self.x.create_window(window, transient_for)?;
+        self.windows.add_pending(parent, window, key_map, Instant::now());
+        Ok(())
+    }
+
+    fn expire_windows(&mut self) -> Result<(), Error> {
+        let expired = self
+            .windows
+            .expire(Instant::now(), PENDING_TIMEOUT, EXIT_TIMEOUT);
+
+        for (parent, state) in expired {
+            println!("window timed out: {} {:?}", parent, state);
+            let result = match state {
+                // the child was never moved, only the parent has to go
+                WindowState::Pending { .. } => self.x.destroy_window(parent),
+                // an exiting child has already been unmapped and moved out of
+                // its destroyed parent, so it is only forgotten. Whether it
+                // shows itself again is up to its application
+                _ => Ok(()),
+            };
+
+            if let Err(error) = result {
+                self.handle_error(error)?;
+            }
+        }
+
+        // a selection owner that never answers leaves the paste waiting
+        if let Some(pending) = self.pending_paste.take_if(|pending| pending.since.elapsed() >= PENDING_TIMEOUT) {
+            println!("the {} selection did not arrive in time", pending.paste.selection.name());
+        }
+        self.x.expire_selections(PENDING_TIMEOUT, PASTE_GRACE)?;
+
+        self.x.restore_keycodes(BORROWED_KEYCODE_TIMEOUT)
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    match enabled {
+        true => "on",
+        false => "off",
     }
 }
@@ -0,0 +1,26 @@
+use super::error::Error;
+use super::key_map::Key;
+
+// what typing text needs from a keyboard. XBridge types into a window with
+// it, and the same text can be typed without a display
+pub trait Keyboard {
+    // the key that types the character, which may have been put on a spare
+    // keycode for it. None for a character that can not be typed at all,
+    // and an error when there is no keycode left to put it on
+    fn key_for(&mut self, character: char) -> Result<Option<Key>, Error>;
+    fn send_key(&mut self, key: Key, pressed: bool) -> Result<(), Error>;
+}
+
+// types the text as a press and a release for every character, so the
+// window never sees a key that is held down. The text is typed up to the
+// first character that fails
+pub fn type_text(keyboard: &mut impl Keyboard, text: &str) -> Result<(), Error> {
+    for character in text.chars() {
+        if let Some(key) = keyboard.key_for(character)? {
+            keyboard.send_key(key, true)?;
+            keyboard.send_key(key, false)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::key_map::KeyMap;
+use super::xbridge::WindowHandle;
+
+// the life of a parent, from being created for a child until the child is
+// gone. Every state keeps the child, so it can be found from either window
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum WindowState {
+    // the parent was created, but has not been exposed yet
+    Pending { child: WindowHandle, since: Instant },
+    // the child is inside of the parent and its keys are remapped
+    Wrapped(WindowHandle),
+    // the child was asked to close and has been moved out of the parent
+    Exiting { child: WindowHandle, since: Instant },
+    // the child was given back and should not be wrapped again
+    Released(WindowHandle),
+}
+
+impl WindowState {
+    pub fn child(self) -> WindowHandle {
+        match self {
+            WindowState::Pending { child, .. } => child,
+            WindowState::Wrapped(child) => child,
+            WindowState::Exiting { child, .. } => child,
+            WindowState::Released(child) => child,
+        }
+    }
+}
+
+struct Entry {
+    state: WindowState,
+    key_map: KeyMap,
+    // whether the keys of this window are remapped, they can be
+    // turned off for a single window
+    enabled: bool,
+}
+
+// pairs every parent with its child. Parents are known by the handle
+// create_window returned for them, so the order in which parents are
+// exposed does not matter
+#[derive(Default)]
+pub struct WindowTable {
+    entries: HashMap<WindowHandle, Entry>,
+}
+
+impl WindowTable {
+    pub fn new() -> WindowTable {
+        WindowTable::default()
+    }
+
+    pub fn add_pending(&mut self, parent: WindowHandle, child: WindowHandle, key_map: KeyMap, now: Instant) {
+        let state = WindowState::Pending { child, since: now };
+        self.entries.insert(
+            parent,
+            Entry {
+                state,
+                key_map,
+                enabled: true,
+            },
+        );
+    }
+
+    pub fn state(&self, parent: WindowHandle) -> Option<WindowState> {
+        self.entries.get(&parent).map(|entry| entry.state)
+    }
+
+    // moves a pending parent to wrapped, returning the child that has to be
+    // put into it. Any other parent, or a window that is not a parent at
+    // all, returns None
+    pub fn wrap(&mut self, parent: WindowHandle) -> Option<WindowHandle> {
+        let entry = self.entries.get_mut(&parent)?;
+        match entry.state {
+            WindowState::Pending { child, .. } => {
+                entry.state = WindowState::Wrapped(child);
+                Some(child)
+            }
+            _ => None,
+        }
+    }
+
+    // moves a wrapped parent to exiting, returning its child
+    pub fn start_exit(&mut self, parent: WindowHandle, now: Instant) -> Option<WindowHandle> {
+        let entry = self.entries.get_mut(&parent)?;
+        match entry.state {
+            WindowState::Wrapped(child) => {
+                entry.state = WindowState::Exiting { child, since: now };
+                Some(child)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn release(&mut self, parent: WindowHandle) -> Option<WindowHandle> {
+        let entry = self.entries.get_mut(&parent)?;
+        let child = entry.state.child();
+        entry.state = WindowState::Released(child);
+        Some(child)
+    }
+
+    // the child of a parent, only while it is wrapped
+    pub fn wrapped_child(&self, parent: WindowHandle) -> Option<WindowHandle> {
+        match self.state(parent)? {
+            WindowState::Wrapped(child) => Some(child),
+            _ => None,
+        }
+    }
+
+    // the parent of a child, only while it is wrapped
+    pub fn wrapped_parent(&self, child: WindowHandle) -> Option<WindowHandle> {
+        self.entries
+            .iter()
+            .find(|(_, entry)| entry.state == WindowState::Wrapped(child))
+            .map(|(&parent, _)| parent)
+    }
+
+    // the key map of a parent, only while it is wrapped
+    pub fn key_map(&self, parent: WindowHandle) -> Option<&KeyMap> {
+        let entry = self.entries.get(&parent)?;
+        match entry.state {
+            WindowState::Wrapped(_) => Some(&entry.key_map),
+            _ => None,
+        }
+    }
+
+    pub fn set_key_map(&mut self, parent: WindowHandle, key_map: KeyMap) {
+        if let Some(entry) = self.entries.get_mut(&parent) {
+            entry.key_map = key_map;
+        }
+    }
+
+    pub fn set_enabled(&mut self, parent: WindowHandle, enabled: bool) {
+        if let Some(entry) = self.entries.get_mut(&parent) {
+            entry.enabled = enabled;
+        }
+    }
+
+    pub fn is_enabled(&self, parent: WindowHandle) -> bool {
+        self.entries.get(&parent).is_some_and(|entry| entry.enabled)
+    }
+
+    // the wrapped parent a window belongs to, the window can be
+    // either the parent or the child
+    pub fn find_wrapped(&self, window: WindowHandle) -> Option<WindowHandle> {
+        match self.wrapped_child(window) {
+            Some(_) => Some(window),
+            None => self.wrapped_parent(window),
+        }
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = (WindowHandle, WindowState)> + '_ {
+        self.entries.iter().map(|(&parent, entry)| (parent, entry.state))
+    }
+
+    // whether the child is known in any state, in which case it should
+    // not be given another parent
+    pub fn has_child(&self, child: WindowHandle) -> bool {
+        self.entries.values().any(|entry| entry.state.child() == child)
+    }
+
+    pub fn wrapped(&self) -> impl Iterator<Item = (WindowHandle, WindowHandle)> + '_ {
+        self.entries.iter().filter_map(|(&parent, entry)| match entry.state {
+            WindowState::Wrapped(child) => Some((parent, child)),
+            _ => None,
+        })
+    }
+
+    // forgets the entry of a window that was destroyed, returning the parent
+    // and the state it was in. Exiting and released children have already
+    // been moved out of their parent, so for them only the child being
+    // destroyed ends the entry
+    pub fn remove_destroyed(&mut self, window: WindowHandle) -> Option<(WindowHandle, WindowState)> {
+        let parent = match self.state(window) {
+            Some(WindowState::Pending { .. } | WindowState::Wrapped(_)) => window,
+            _ => *self
+                .entries
+                .iter()
+                .find(|(_, entry)| entry.state.child() == window)?
+                .0,
+        };
+
+        let entry = self.entries.remove(&parent)?;
+        Some((parent, entry.state))
+    }
+
+    // ends the pending and exiting entries that have been in that state for
+    // longer than their timeout, returning the parent and the state each of
+    // them was in. A pending child is released, as a parent that was never
+    // exposed would likely not be exposed the next time either
+    pub fn expire(
+        &mut self,
+        now: Instant,
+        pending_timeout: Duration,
+        exit_timeout: Duration,
+    ) -> Vec<(WindowHandle, WindowState)> {
+        let expired: Vec<(WindowHandle, WindowState)> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| match entry.state {
+                WindowState::Pending { since, .. } => now.duration_since(since) >= pending_timeout,
+                WindowState::Exiting { since, .. } => now.duration_since(since) >= exit_timeout,
+                _ => false,
+            })
+            .map(|(&parent, entry)| (parent, entry.state))
+            .collect();
+
+        for (parent, state) in &expired {
+            if let WindowState::Pending { child, .. } = state {
+                self.entries.get_mut(parent).unwrap().state = WindowState::Released(*child);
+            } else {
+                self.entries.remove(parent);
+            }
+        }
+
+        expired
+    }
+}
@@ -0,0 +1,107 @@
+// the types of _NET_WM_WINDOW_TYPE that are in the EWMH spec
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowType {
+    Normal,
+    Dialog,
+    Utility,
+    Toolbar,
+    Splash,
+    Menu,
+    DropdownMenu,
+    PopupMenu,
+    Tooltip,
+    Notification,
+    Combo,
+    Dnd,
+    Dock,
+    Desktop,
+}
+
+// how a new window should be treated, only normal and transient
+// windows are ever given a parent
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowKind {
+    Normal,
+    // dialogs and other windows that belong to another window, their
+    // WM_TRANSIENT_FOR has to point at the parent of that window
+    Transient,
+    // menus, tooltips and the like, which are short lived and would
+    // break if they were put into a parent
+    Popup,
+    // windows the window manager does not manage, so neither do we
+    OverrideRedirect,
+}
+
+const TYPE_NAMES: [(&str, WindowType); 14] = [
+    ("NORMAL", WindowType::Normal),
+    ("DIALOG", WindowType::Dialog),
+    ("UTILITY", WindowType::Utility),
+    ("TOOLBAR", WindowType::Toolbar),
+    ("SPLASH", WindowType::Splash),
+    ("MENU", WindowType::Menu),
+    ("DROPDOWN_MENU", WindowType::DropdownMenu),
+    ("POPUP_MENU", WindowType::PopupMenu),
+    ("TOOLTIP", WindowType::Tooltip),
+    ("NOTIFICATION", WindowType::Notification),
+    ("COMBO", WindowType::Combo),
+    ("DND", WindowType::Dnd),
+    ("DOCK", WindowType::Dock),
+    ("DESKTOP", WindowType::Desktop),
+];
+
+impl WindowType {
+    // takes the name of the atom, such as _NET_WM_WINDOW_TYPE_DIALOG
+    pub fn from_atom_name(name: &str) -> Option<WindowType> {
+        let name = name.strip_prefix("_NET_WM_WINDOW_TYPE_")?;
+        TYPE_NAMES
+            .iter()
+            .find(|(type_name, _)| *type_name == name)
+            .map(|(_, window_type)| *window_type)
+    }
+
+    pub fn name(self) -> &'static str {
+        TYPE_NAMES
+            .iter()
+            .find(|(_, window_type)| *window_type == self)
+            .map(|(name, _)| *name)
+            .expect("every window type has a name")
+    }
+}
+
+impl WindowKind {
+    // a window without a type is normal, unless it is transient for another
+    // window, in which case EWMH says it should be treated as a dialog
+    pub fn classify(
+        window_type: Option<WindowType>,
+        is_transient: bool,
+        override_redirect: bool,
+    ) -> WindowKind {
+        if override_redirect {
+            return WindowKind::OverrideRedirect;
+        }
+
+        match window_type {
+            Some(
+                WindowType::Splash
+                | WindowType::Menu
+                | WindowType::DropdownMenu
+                | WindowType::PopupMenu
+                | WindowType::Tooltip
+                | WindowType::Notification
+                | WindowType::Combo
+                | WindowType::Dnd
+                | WindowType::Dock
+                | WindowType::Desktop,
+            ) => WindowKind::Popup,
+            Some(WindowType::Dialog | WindowType::Utility | WindowType::Toolbar) => {
+                WindowKind::Transient
+            }
+            Some(WindowType::Normal) | None if is_transient => WindowKind::Transient,
+            Some(WindowType::Normal) | None => WindowKind::Normal,
+        }
+    }
+
+    pub fn is_wrapped(self) -> bool {
+        matches!(self, WindowKind::Normal | WindowKind::Transient)
+    }
+}
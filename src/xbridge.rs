@@ -1,9 +1,11 @@
-use std::collections::HashMap;
-use std::ffi::{c_long, c_char, c_void, CStr, CString};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_int, c_long, c_ulong, c_char, c_void, CStr, CString};
 use std::mem::{self, MaybeUninit};
+use std::os::unix::io::RawFd;
 use std::ptr;
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use x11_dl::xlib::{
     AnyKey, AnyModifier, AnyPropertyType, Atom, CWEventMask, ConfigureRequest, CurrentTime,
@@ -11,20 +13,143 @@ use x11_dl::xlib::{
     StructureNotifyMask, SubstructureNotifyMask, True, Window, XClassHint, XClientMessageEvent,
     XConfigureRequestEvent, XDestroyWindowEvent, XEvent, XExposeEvent, XKeyEvent, XReparentEvent,
     XResizeRequestEvent, XSetWindowAttributes, XWindowAttributes, ClientMessage, ClientMessageData,
-    NoEventMask, FocusChangeMask, XEnterWindowEvent, XFocusChangeEvent, NotifyInferior, RevertToNone
+    NoEventMask, FocusChangeMask, XEnterWindowEvent, XFocusChangeEvent, NotifyInferior, RevertToNone,
+    NotifyNormal, NotifyPointer, NotifyPointerRoot, NotifyDetailNone, RevertToParent, InputHint,
+    Time, XWMHints, KeyReleaseMask, ShiftMask, PropertyChangeMask, PropModeReplace, XPropertyEvent, XUrgencyHint,
+    SubstructureRedirectMask, XMapRequestEvent, XConfigureEvent, CWWidth, CWHeight, XErrorEvent,
+    BadWindow, ButtonPressMask, ButtonReleaseMask, XButtonEvent, KeySym, XMappingEvent, ControlMask,
+    XSelectionEvent, XSelectionRequestEvent, XSelectionClearEvent, XA_PRIMARY, XA_STRING, XA_ATOM,
+    PropertyNewValue, PropertyDelete, XA_WINDOW
 };
+use x11_dl::keysym::XK_v;
 
 use x11_dl::xlib::Xlib;
 
-use super::key_map::{Key, KeyMap};
+use super::focus::{self, FocusBackend, FocusModel};
+use super::typing::{self, Keyboard};
+use super::window_table::WindowTable;
+use super::window_type::WindowType;
+use super::error::Error;
+use super::key_map::{Key, KeyMap, Selection, MOUSE_BUTTON};
 
 // prevent outside from having to import x11 libraries
 pub type WindowHandle = Window;
+pub type AtomHandle = Atom;
+
+// the properties of the child that are copied onto its parent, so that
+// taskbars, window switchers and window manager rules see the child
+const MIRRORED_PROPERTIES: [&str; 8] = [
+    "WM_NAME",
+    "_NET_WM_NAME",
+    "WM_CLASS",
+    "_NET_WM_ICON",
+    "_NET_WM_PID",
+    "WM_WINDOW_ROLE",
+    "_NET_WM_WINDOW_TYPE",
+    "WM_NORMAL_HINTS",
+];
+
+const PAUSED_SUFFIX: &str = " [paused]";
+
+// the property a selection is converted to on the selection window
+const TRANSFER_PROPERTY: &str = "X11_KEY_REMAPPER_SELECTION";
+
+// set on every parent to the child it wraps, so that another process,
+// such as list-windows, can tell the parents apart from client windows
+const PARENT_PROPERTY: &str = "X11_KEY_REMAPPER_PARENT";
+
+// read the whole property at once, in 32 bit units as the server counts
+const MAX_PROPERTY_LENGTH: c_long = 0x1fff_ffff;
+
+// the size of the chunks a selection that is too large for a single
+// request is sent in, well below the size of a request
+const INCR_CHUNK_SIZE: usize = 0x10000;
+
+// an error the X server sent back for one of the requests. Windows of
+// other clients can be destroyed at any time, so these are expected
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct XError {
+    pub error_code: u8,
+    pub request_code: u8,
+    pub resource: u64,
+    // the serial of the request that failed
+    pub serial: u64,
+}
+
+impl XError {
+    pub fn is_bad_window(&self) -> bool {
+        self.error_code == BadWindow
+    }
+}
+
+// xlib calls the error handler without any way to pass state to it, so the
+// errors are kept here until the request that caused them checks for them.
+// Every display counts its requests on its own, so an error is kept with
+// the address of the display it came from
+static ERRORS: Mutex<Vec<(usize, XError)>> = Mutex::new(Vec::new());
+
+unsafe extern "C" fn error_handler(display: *mut Display, event: *mut XErrorEvent) -> c_int {
+    let event = &*event;
+    if let Ok(mut errors) = ERRORS.lock() {
+        errors.push((
+            display as usize,
+            XError {
+                error_code: event.error_code,
+                request_code: event.request_code,
+                resource: event.resourceid,
+                serial: event.serial,
+            },
+        ));
+    }
+    0
+}
+
+// xlib exits as soon as this returns, there is no way to recover
+// from losing the connection
+unsafe extern "C" fn io_error_handler(_display: *mut Display) -> c_int {
+    eprintln!("lost the connection to the X server");
+    std::process::exit(1);
+}
+
+// takes the errors of the display that were caused by requests with a
+// serial of at least since, the others are left for whoever asks for them
+fn take_errors(display: *mut Display, since: u64) -> Vec<XError> {
+    let mut errors = match ERRORS.lock() {
+        Ok(errors) => errors,
+        Err(_) => return Vec::new(),
+    };
+
+    let (taken, kept) = mem::take(&mut *errors)
+        .into_iter()
+        .partition(|&(from, error)| from == display as usize && error.serial >= since);
+    *errors = kept;
+    taken.into_iter().map(|(_, error)| error).collect()
+}
 
 pub enum XBridgeEvent {
+    // time is the server time of the press, which selections are
+    // owned and converted with
     KeyPress {
         key: Key,
         parent: WindowHandle,
+        time: u64,
+    },
+    // only the input window of the key inspector selects these, the
+    // releases of grabbed keys are not passed on
+    KeyRelease {
+        window: WindowHandle,
+        key: Key,
+    },
+    // the button is a key with the MOUSE_BUTTON bit, so it can be
+    // looked up in a key map
+    ButtonPress {
+        parent: WindowHandle,
+        button: Key,
+        time: u64,
+    },
+    ButtonRelease {
+        parent: WindowHandle,
+        button: Key,
     },
     Expose {
         parent: WindowHandle,
@@ -45,7 +170,65 @@ pub enum XBridgeEvent {
     },
     ParentFocus {
         parent: WindowHandle
-    }
+    },
+    // the window manager asked the parent to take focus, time is the
+    // timestamp of the event that caused it and has to be passed on
+    TakeFocus {
+        parent: WindowHandle,
+        time: u64,
+    },
+    // one of the synced properties of a watched child or of a parent changed
+    PropertyChange {
+        window: WindowHandle,
+        property: AtomHandle,
+    },
+    // the window asked the window manager to change its _NET_WM_STATE,
+    // data is the data of the client message
+    StateRequest {
+        window: WindowHandle,
+        data: [i64; 5],
+    },
+    // a child of a parent asked to be resized, as parents redirect
+    // their children the same way a window manager does
+    ChildConfigureRequest {
+        parent: WindowHandle,
+        child: WindowHandle,
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    ChildMapRequest {
+        child: WindowHandle,
+    },
+    Selection(SelectionEvent),
+}
+
+// the events of reading a selection and of owning one, which are passed
+// back to handle_selection_event
+pub enum SelectionEvent {
+    // the owner converted the selection, or refused to when there is no
+    // property
+    Notify {
+        property: Option<AtomHandle>,
+    },
+    // the owner stored the next chunk of an INCR transfer
+    NewValue,
+    // a requestor deleted the property of an INCR transfer it reads from
+    // us, so the next chunk can be stored
+    PropertyDelete {
+        requestor: WindowHandle,
+        property: AtomHandle,
+    },
+    // another window asks for a selection that is owned
+    Request {
+        requestor: WindowHandle,
+        selection: AtomHandle,
+        target: AtomHandle,
+        property: AtomHandle,
+        time: u64,
+    },
+    Clear {
+        selection: AtomHandle,
+    },
 }
 
 pub struct XBridge {
@@ -56,7 +239,88 @@ pub struct XBridge {
     pid_atom: Option<Atom>,
     close_window_atom: Atom,
     take_focus_atom: Atom,
-    wm_protocols_atom: Atom
+    wm_protocols_atom: Atom,
+    mirrored_atoms: Vec<Atom>,
+    wm_hints_atom: Option<Atom>,
+    wm_state_atom: Option<Atom>,
+    window_type_atom: Option<Atom>,
+    // the parents made by create_window, so they can be told apart from
+    // the windows of other clients once they carry the child's properties
+    created_windows: HashSet<Window>,
+    // parents whose title says that remapping is paused for them
+    paused_windows: HashSet<Window>,
+    title_atoms: Vec<Atom>,
+    client_list_atom: Option<Atom>,
+    // the WM_STATE of ICCCM, which is not _NET_WM_STATE
+    icccm_state_atom: Option<Atom>,
+    // keycodes without keysyms that were given one, to type a character
+    // that is not on the keyboard
+    borrowed_keycodes: Vec<BorrowedKeycode>,
+    // an unmapped window that selections are read with and owned by,
+    // made the first time a selection is used
+    selection_window: Option<Window>,
+    clipboard_atom: Atom,
+    utf8_atom: Atom,
+    targets_atom: Atom,
+    incr_atom: Atom,
+    transfer_atom: Atom,
+    parent_atom: Atom,
+    // the selection that is being read, a new request replaces one
+    // that never finished
+    transfer: Option<SelectionTransfer>,
+    // the selections that were owned for a paste, given to any window
+    // that asks until another client owns them
+    owned_selections: HashMap<Atom, OwnedSelection>,
+    // the selections that are sent with INCR, by requestor and property
+    outgoing: HashMap<(Window, Atom), OutgoingTransfer>,
+}
+
+struct SelectionTransfer {
+    selection: Atom,
+    target: Atom,
+    // the time of the key press the selection is read for
+    time: Time,
+    // an INCR transfer comes in chunks until an empty one
+    incr: bool,
+    data: Vec<u8>,
+    since: Instant,
+}
+
+struct OwnedSelection {
+    text: String,
+    // the text the selection had before the paste, which it gets back
+    // once the paste was served
+    restore: Option<String>,
+    // the window the paste key was sent to and when. Other clients, such
+    // as clipboard managers, may ask for the text before that window does,
+    // so only the window itself or the grace period ends the paste
+    pasted: Option<(Window, Instant)>,
+    // the time the selection was owned at
+    time: Time,
+    since: Instant,
+}
+
+struct OutgoingTransfer {
+    selection: Atom,
+    target: Atom,
+    data: Vec<u8>,
+    // how much of the data was stored on the requestor so far
+    sent: usize,
+    // the events we selected on the requestor before the transfer
+    event_mask: c_long,
+    since: Instant,
+}
+
+// the keyboard of the display, typing into one window
+struct TypingInto<'x> {
+    x: &'x mut XBridge,
+    window: Window,
+}
+
+struct BorrowedKeycode {
+    keycode: u8,
+    keysym: KeySym,
+    since: Instant,
 }
 
 impl Drop for XBridge {
@@ -64,45 +328,83 @@ impl Drop for XBridge {
         for (&window, keys) in &self.grabbed_keys {
             ungrab_keys(&self.xlib, self.display, window, keys);
         }
+        let _ = self.restore_keycodes(Duration::ZERO);
 
         for screen in &self.window_creation_listening_screens {
-            free_listen_window_creation(self.display, screen.clone());
+            free_listen_window_creation(self.display, *screen);
         }
     }
 }
 
 impl XBridge {
-    pub fn init() -> Result<XBridge, ()> {
+    // connects to the named display, or to $DISPLAY when there is no name
+    pub fn init(display_name: Option<&str>) -> Result<XBridge, Error> {
         let display;
-        let xlib = match Xlib::open() {
-            Ok(xlib) => xlib,
-            Err(_) => return Err(()),
+        let xlib = Xlib::open()?;
+        let connection_error = || Error::DisplayConnection {
+            display: display_name
+                .map(|name| name.to_string())
+                .or_else(|| std::env::var("DISPLAY").ok()),
         };
 
         unsafe {
-            display = (xlib.XOpenDisplay)(ptr::null());
+            let name = match display_name {
+                Some(name) => Some(CString::new(name).map_err(|_| connection_error())?),
+                None => None,
+            };
+            display = (xlib.XOpenDisplay)(name.as_ref().map_or(ptr::null(), |name| name.as_ptr()));
             if display.is_null() {
-                return Err(());
+                return Err(connection_error());
             }
+
+            // the default handlers exit the process on any error, even for
+            // a window that was destroyed before we got to it
+            (xlib.XSetErrorHandler)(Some(error_handler));
+            (xlib.XSetIOErrorHandler)(Some(io_error_handler));
         }
 
-        let pid_atom = intern_atom(&xlib, display, "_NET_WM_PID");
-        let close_window_atom = match intern_atom(&xlib, display, "WM_DELETE_WINDOW") {
-            Some(atom) => atom,
-            None => return Err(())
+        // there is no XBridge yet to close the display when it is dropped,
+        // so a missing atom closes it here
+        let required_atom = |name| match intern_atom(&xlib, display, name) {
+            Some(atom) => Ok(atom),
+            None => {
+                unsafe {
+                    (xlib.XCloseDisplay)(display);
+                }
+                Err(Error::MissingAtom { name })
+            }
         };
+
+        let pid_atom = intern_atom(&xlib, display, "_NET_WM_PID");
+        let close_window_atom = required_atom("WM_DELETE_WINDOW")?;
         println!("close atom: {}", close_window_atom);
 
-        let take_focus_atom = match intern_atom(&xlib, display, "WM_TAKE_FOCUS") {
-            Some(atom) => atom,
-            None => return Err(())
-        };
+        let take_focus_atom = required_atom("WM_TAKE_FOCUS")?;
         println!("focus atom: {}", take_focus_atom);
 
-        let wm_protocols_atom = match intern_atom(&xlib, display, "WM_PROTOCOLS") {
-            Some(atom) => atom,
-            None => return Err(())
-        };
+        let wm_protocols_atom = required_atom("WM_PROTOCOLS")?;
+
+        let mirrored_atoms = MIRRORED_PROPERTIES
+            .iter()
+            .filter_map(|name| intern_atom(&xlib, display, name))
+            .collect();
+        let client_list_atom = intern_atom(&xlib, display, "_NET_CLIENT_LIST");
+        let icccm_state_atom = intern_atom(&xlib, display, "WM_STATE");
+
+        let wm_hints_atom = intern_atom(&xlib, display, "WM_HINTS");
+        let wm_state_atom = intern_atom(&xlib, display, "_NET_WM_STATE");
+        let window_type_atom = intern_atom(&xlib, display, "_NET_WM_WINDOW_TYPE");
+        let title_atoms = ["WM_NAME", "_NET_WM_NAME"]
+            .iter()
+            .filter_map(|name| intern_atom(&xlib, display, name))
+            .collect();
+
+        let clipboard_atom = required_atom("CLIPBOARD")?;
+        let utf8_atom = required_atom("UTF8_STRING")?;
+        let targets_atom = required_atom("TARGETS")?;
+        let incr_atom = required_atom("INCR")?;
+        let transfer_atom = required_atom(TRANSFER_PROPERTY)?;
+        let parent_atom = required_atom(PARENT_PROPERTY)?;
 
         Ok(XBridge {
             display,
@@ -112,30 +414,97 @@ impl XBridge {
             pid_atom,
             close_window_atom,
             take_focus_atom,
-            wm_protocols_atom
+            wm_protocols_atom,
+            mirrored_atoms,
+            wm_hints_atom,
+            wm_state_atom,
+            window_type_atom,
+            created_windows: HashSet::new(),
+            paused_windows: HashSet::new(),
+            title_atoms,
+            client_list_atom,
+            icccm_state_atom,
+            borrowed_keycodes: Vec::new(),
+            selection_window: None,
+            clipboard_atom,
+            utf8_atom,
+            targets_atom,
+            incr_atom,
+            transfer_atom,
+            parent_atom,
+            transfer: None,
+            owned_selections: HashMap::new(),
+            outgoing: HashMap::new(),
         })
     }
 
-    pub fn focus_window(&self, window: WindowHandle) {
+    // runs the requests and waits for the server to process them, so that
+    // an error they caused is returned instead of turning up later on. The
+    // errors of requests from before are told apart by their serial, and
+    // are left for take_async_errors
+    fn checked<T>(&self, requests: impl FnOnce() -> T) -> Result<T, Error> {
+        let first = unsafe { (self.xlib.XNextRequest)(self.display) };
+        let value = requests();
         unsafe {
-            let mut revert_to = 0;
-            let mut focus_window = 0;
+            (self.xlib.XSync)(self.display, False);
+        }
 
-            /*
-            (self.xlib.XGetInputFocus)(self.display, &mut revert_to, &mut focus_window);
-            if window == focus_window.try_into().unwrap() {
-                return;
+        match take_errors(self.display, first).into_iter().next() {
+            Some(error) => Err(Error::Protocol(error)),
+            None => Ok(value),
+        }
+    }
+
+    // runs requests that nothing waits on, such as events sent to other
+    // windows. They are not synced, an error they cause is only returned
+    // by take_async_errors
+    fn queued<T>(&self, requests: impl FnOnce() -> T) -> Result<T, Error> {
+        Ok(requests())
+    }
+
+    // the errors of queued requests, and of requests that failed before a
+    // checked one was made
+    pub fn take_async_errors(&self) -> Vec<XError> {
+        take_errors(self.display, 0)
+    }
+
+    // passes the focus of a parent on to its child, see focus::pass_focus
+    pub fn pass_focus(
+        &self,
+        windows: &WindowTable,
+        parent: WindowHandle,
+        time: Option<u64>,
+    ) -> Result<Option<WindowHandle>, Error> {
+        self.checked(|| focus::pass_focus(self, windows, parent, time))
+    }
+
+    fn supports_protocol(&self, window: WindowHandle, protocol: Atom) -> bool {
+        unsafe {
+            let mut protocols = ptr::null_mut();
+            let mut count = 0;
+            if (self.xlib.XGetWMProtocols)(self.display, window, &mut protocols, &mut count) == 0 {
+                return false;
             }
-            */
 
-            (self.xlib.XSetInputFocus)(self.display, window, RevertToNone, CurrentTime);
+            let supported = std::slice::from_raw_parts(protocols, count as usize).contains(&protocol);
+            (self.xlib.XFree)(protocols as *mut c_void);
+            supported
         }
     }
 
-    pub fn wait_next_event(&self) -> XBridgeEvent {
+    // the file descriptor of the connection, it becomes readable when the
+    // server has sent something
+    pub fn connection_fd(&self) -> RawFd {
+        unsafe { (self.xlib.XConnectionNumber)(self.display) }
+    }
+
+    // returns the next event xlib has already received, without waiting for
+    // one. Xlib also reads events while it waits for replies, so this has to
+    // be called until it returns None before waiting on connection_fd
+    pub fn next_event(&self) -> Option<XBridgeEvent> {
         unsafe {
             let mut event: MaybeUninit<XEvent> = MaybeUninit::uninit();
-            loop {
+            while (self.xlib.XPending)(self.display) > 0 {
                 (self.xlib.XNextEvent)(self.display, event.as_mut_ptr());
 
                 match event.assume_init().type_ {
@@ -143,49 +512,139 @@ impl XBridge {
                         let event = event.as_mut_ptr() as *mut XKeyEvent;
                         let state = (&*event).state;
                         let key_code = (&*event).keycode;
-                        return XBridgeEvent::KeyPress {
+                        return Some(XBridgeEvent::KeyPress {
                             key: Key {
                                 state,
                                 code: key_code,
                             },
                             parent: (&*event).window,
+                            time: (&*event).time,
+                        });
+                    }
+                    x11_dl::xlib::KeyRelease => {
+                        let event = event.as_mut_ptr() as *mut XKeyEvent;
+                        return Some(XBridgeEvent::KeyRelease {
+                            key: Key {
+                                state: (&*event).state,
+                                code: (&*event).keycode,
+                            },
+                            window: (&*event).window,
+                        });
+                    }
+                    x11_dl::xlib::ButtonPress | x11_dl::xlib::ButtonRelease => {
+                        let event = &*(event.as_mut_ptr() as *mut XButtonEvent);
+                        let button = Key {
+                            state: event.state | MOUSE_BUTTON,
+                            code: event.button,
                         };
+                        return Some(match event.type_ {
+                            x11_dl::xlib::ButtonPress => XBridgeEvent::ButtonPress {
+                                parent: event.window,
+                                button,
+                                time: event.time,
+                            },
+                            _ => XBridgeEvent::ButtonRelease {
+                                parent: event.window,
+                                button,
+                            },
+                        });
                     }
                     x11_dl::xlib::Expose => {
                         let event = event.as_mut_ptr() as *mut XExposeEvent;
-                        return XBridgeEvent::Expose {
+                        return Some(XBridgeEvent::Expose {
                             parent: (&*event).window,
-                        };
+                        });
                     }
                     x11_dl::xlib::ConfigureNotify => {
                         let event = event.as_mut_ptr() as *mut XConfigureRequestEvent;
-                        return XBridgeEvent::ConfigureNotify {
+                        return Some(XBridgeEvent::ConfigureNotify {
                             parent: (&*event).window,
-                            width: (&*event).width.try_into().unwrap(),
-                            height: (&*event).height.try_into().unwrap(),
-                        };
+                            width: dimension((&*event).width),
+                            height: dimension((&*event).height),
+                        });
                     }
                     x11_dl::xlib::ReparentNotify => {
                         let event = event.as_mut_ptr() as *mut XReparentEvent;
-                        return XBridgeEvent::ReparentNotify {
+                        return Some(XBridgeEvent::ReparentNotify {
                             window: (&*event).window,
-                        };
+                        });
                     }
                     x11_dl::xlib::ClientMessage => {
                         let event = event.as_mut_ptr() as *mut XClientMessageEvent;
-                        let message_atom = AsMut::<[u64]>::as_mut(&mut (&mut *event).data)[0];
+
+                        // state requests are sent to the root window, not to the
+                        // window that wants its state changed
+                        if Some((&*event).message_type) == self.wm_state_atom {
+                            let data = &(&*event).data;
+                            let mut values = [0; 5];
+                            for (i, value) in values.iter_mut().enumerate() {
+                                *value = data.get_long(i);
+                            }
+
+                            return Some(XBridgeEvent::StateRequest {
+                                window: (&*event).window,
+                                data: values,
+                            });
+                        }
+
+                        let message_atom = (&*event).data.get_long(0) as Atom;
 
                         if message_atom == self.close_window_atom {
-                            return XBridgeEvent::DestroyRequest { window: (&*event).window };
+                            return Some(XBridgeEvent::DestroyRequest { window: (&*event).window });
                         }
                         else if message_atom == self.take_focus_atom {
-                            todo!();
+                            let time = (&*event).data.get_long(1) as Time;
+                            return Some(XBridgeEvent::TakeFocus {
+                                parent: (&*event).window,
+                                time,
+                            });
                         }
                     }
+                    x11_dl::xlib::ConfigureRequest => {
+                        let event = event.as_mut_ptr() as *mut XConfigureRequestEvent;
+                        let value_mask = (&*event).value_mask;
+                        let width = dimension((&*event).width);
+                        let height = dimension((&*event).height);
+
+                        return Some(XBridgeEvent::ChildConfigureRequest {
+                            parent: (&*event).parent,
+                            child: (&*event).window,
+                            width: (value_mask & CWWidth as u64 != 0).then_some(width),
+                            height: (value_mask & CWHeight as u64 != 0).then_some(height),
+                        });
+                    }
+                    x11_dl::xlib::MapRequest => {
+                        let event = event.as_mut_ptr() as *mut XMapRequestEvent;
+                        return Some(XBridgeEvent::ChildMapRequest {
+                            child: (&*event).window,
+                        });
+                    }
                     x11_dl::xlib::DestroyNotify => {
                         let event = event.as_mut_ptr() as *mut XDestroyWindowEvent;
-                        return XBridgeEvent::DestroyNotify {
+                        return Some(XBridgeEvent::DestroyNotify {
                             window: (&*event).window
+                        });
+                    }
+                    x11_dl::xlib::PropertyNotify => {
+                        let event = event.as_mut_ptr() as *mut XPropertyEvent;
+                        if Some((&*event).window) == self.selection_window {
+                            if (&*event).atom == self.transfer_atom && (&*event).state == PropertyNewValue {
+                                return Some(XBridgeEvent::Selection(SelectionEvent::NewValue));
+                            }
+                            continue;
+                        }
+                        let outgoing = ((&*event).window, (&*event).atom);
+                        if (&*event).state == PropertyDelete && self.outgoing.contains_key(&outgoing) {
+                            return Some(XBridgeEvent::Selection(SelectionEvent::PropertyDelete {
+                                requestor: outgoing.0,
+                                property: outgoing.1,
+                            }));
+                        }
+                        if self.is_synced_property((&*event).atom) {
+                            return Some(XBridgeEvent::PropertyChange {
+                                window: (&*event).window,
+                                property: (&*event).atom,
+                            });
                         }
                     }
                     x11_dl::xlib::FocusIn => {
@@ -193,44 +652,87 @@ impl XBridge {
 
                         // grab keys will cause this event to occur, we want to
                         // filter them out so we can properly know when we need to
-                        // refocus the child
-                        if (&*event).detail == NotifyInferior {
+                        // refocus the child. Focus moving into the child, focus that
+                        // only follows the pointer, and the focus changes of grabs
+                        // are not the window manager focusing the parent either
+                        let detail = (&*event).detail;
+                        let ignored_detail = detail == NotifyInferior
+                            || detail == NotifyPointer
+                            || detail == NotifyPointerRoot
+                            || detail == NotifyDetailNone;
+                        if ignored_detail || (&*event).mode != NotifyNormal {
                             continue;
                         }
 
-                        return XBridgeEvent::ParentFocus {
+                        return Some(XBridgeEvent::ParentFocus {
                             parent: (&*event).window
+                        });
+                    }
+                    x11_dl::xlib::SelectionNotify => {
+                        let event = &*(event.as_mut_ptr() as *mut XSelectionEvent);
+                        if Some(event.requestor) == self.selection_window {
+                            return Some(XBridgeEvent::Selection(SelectionEvent::Notify {
+                                property: (event.property != 0).then_some(event.property),
+                            }));
                         }
                     }
+                    x11_dl::xlib::SelectionRequest => {
+                        let event = &*(event.as_mut_ptr() as *mut XSelectionRequestEvent);
+                        return Some(XBridgeEvent::Selection(SelectionEvent::Request {
+                            requestor: event.requestor,
+                            selection: event.selection,
+                            target: event.target,
+                            property: event.property,
+                            time: event.time,
+                        }));
+                    }
+                    x11_dl::xlib::SelectionClear => {
+                        let event = &*(event.as_mut_ptr() as *mut XSelectionClearEvent);
+                        return Some(XBridgeEvent::Selection(SelectionEvent::Clear {
+                            selection: event.selection,
+                        }));
+                    }
+                    // keysyms are looked up in a copy of the keyboard mapping,
+                    // which has to be updated when the mapping changes
+                    x11_dl::xlib::MappingNotify => {
+                        (self.xlib.XRefreshKeyboardMapping)(event.as_mut_ptr() as *mut XMappingEvent);
+                    }
                     _ => {} // we don't need this event, just loop again
                 }
             }
         }
+
+        None
     }
 
     fn kill_message_child() {
         todo!();
     }
 
-    pub fn resize_to_parent(&self, child: WindowHandle, parent: WindowHandle) {
-        unsafe {
+    pub fn resize_to_parent(&self, child: WindowHandle, parent: WindowHandle) -> Result<(), Error> {
+        let (width, height) = self.window_size(parent)?;
+        self.resize_to(child, width, height)
+    }
+
+    pub fn window_size(&self, window: WindowHandle) -> Result<(u32, u32), Error> {
+        self.checked(|| unsafe {
             let mut attributes: MaybeUninit<XWindowAttributes> = mem::zeroed();
-            (self.xlib.XGetWindowAttributes)(self.display, parent, attributes.as_mut_ptr());
+            (self.xlib.XGetWindowAttributes)(self.display, window, attributes.as_mut_ptr());
             (self.xlib.XSync)(self.display, False);
-            let width = attributes.assume_init().width.try_into().unwrap();
-            let height = attributes.assume_init().height.try_into().unwrap();
+            let width = dimension(attributes.assume_init().width);
+            let height = dimension(attributes.assume_init().height);
 
-            self.resize_to(child, width, height);
-        }
+            (width, height)
+        })
     }
 
-    pub fn resize_to(&self, window: WindowHandle, width: u32, height: u32) {
-        unsafe {
+    pub fn resize_to(&self, window: WindowHandle, width: u32, height: u32) -> Result<(), Error> {
+        self.checked(|| unsafe {
             (self.xlib.XResizeWindow)(self.display, window, width, height);
-        }
+        })
     }
 
-    pub fn grab_keys(&mut self, window: WindowHandle, key_map: KeyMap) {
+    pub fn grab_keys(&mut self, window: WindowHandle, key_map: KeyMap) -> Result<(), Error> {
         if let Some(keys_map) = self.grabbed_keys.get(&window) {
             // ungrab before removing them, so if there is an error
             // they can still be ungrabbed
@@ -241,32 +743,88 @@ impl XBridge {
         // we can just get rid of it
         self.grabbed_keys.remove(&window);
 
-        // grab all of the keys
-        for key in key_map.keys() {
-            unsafe {
-                (self.xlib.XGrabKey)(
-                    self.display,
-                    key.code.try_into().unwrap(),
-                    key.state,
-                    window,
-                    False,
-                    GrabModeAsync,
-                    GrabModeAsync,
-                );
+        // grab all of the keys, the buttons are grabbed until they are
+        // released so the release comes to the parent as well
+        self.checked(|| {
+            for key in key_map.keys() {
+                unsafe {
+                    if key.is_button() {
+                        (self.xlib.XGrabButton)(
+                            self.display,
+                            key.code,
+                            grab_state(*key),
+                            window,
+                            False,
+                            (ButtonPressMask | ButtonReleaseMask) as u32,
+                            GrabModeAsync,
+                            GrabModeAsync,
+                            0,
+                            0,
+                        );
+                    } else {
+                        (self.xlib.XGrabKey)(
+                            self.display,
+                            key.code.try_into().unwrap(),
+                            grab_state(*key),
+                            window,
+                            False,
+                            GrabModeAsync,
+                            GrabModeAsync,
+                        );
+                    }
+                }
             }
-        }
+        })?;
 
         // grab the keys before setting the map, so they are not
         // removed if they are never set
         self.grabbed_keys.insert(window, key_map);
+        Ok(())
     }
 
     pub fn default_screen(&self) -> i32 {
         unsafe { (self.xlib.XDefaultScreen)(self.display) }
     }
 
-    pub fn create_window(&mut self, screen: i32) -> WindowHandle {
+    pub fn screen_count(&self) -> i32 {
+        unsafe { (self.xlib.XScreenCount)(self.display) }
+    }
+
+    pub fn screen_of(&self, window: WindowHandle) -> Result<i32, Error> {
+        self.checked(|| unsafe {
+            let mut attributes: MaybeUninit<XWindowAttributes> = MaybeUninit::uninit();
+            if (self.xlib.XGetWindowAttributes)(self.display, window, attributes.as_mut_ptr()) == 0 {
+                return self.default_screen();
+            }
+            (self.xlib.XScreenNumberOfScreen)(attributes.assume_init().screen)
+        })
+    }
+
+    // the name xlib connected to, for when there is more than one display
+    pub fn display_name(&self) -> String {
         unsafe {
+            let name = (self.xlib.XDisplayString)(self.display);
+            if name.is_null() {
+                return String::new();
+            }
+            CStr::from_ptr(name).to_string_lossy().into_owned()
+        }
+    }
+
+    // creates the parent for child. The child's properties are copied
+    // before the parent is mapped, as some window managers only look
+    // at the class and type of a window when they first manage it. The
+    // same goes for transient_for, which is what WM_TRANSIENT_FOR of the
+    // parent should be
+    // the parent is created on the same screen as the child, as a window
+    // can not be reparented into a window of another screen
+    pub fn create_window(
+        &mut self,
+        child: WindowHandle,
+        transient_for: Option<WindowHandle>,
+    ) -> Result<WindowHandle, Error> {
+        let screen = self.screen_of(child)?;
+        let window = unsafe {
             // get the root window
             let root = (self.xlib.XRootWindow)(self.display, screen);
             let black = (self.xlib.XBlackPixel)(self.display, screen);
@@ -278,142 +836,1502 @@ impl XBridge {
             (self.xlib.XSelectInput)(
                 self.display,
                 window,
-                StructureNotifyMask | ExposureMask | FocusChangeMask,
+                StructureNotifyMask
+                    | ExposureMask
+                    | FocusChangeMask
+                    | PropertyChangeMask
+                    | SubstructureRedirectMask
+                    | SubstructureNotifyMask,
+            );
+
+            window
+        };
+
+        // the state is only copied from the child before the parent is
+        // mapped, after that it is requested from the window manager. If
+        // the child is already gone, so is the need for its parent
+        let copied = self.checked(|| {
+            self.copy_properties(child, window);
+            if let Some(wm_state) = self.wm_state_atom {
+                self.copy_property(child, window, wm_state);
+            }
+        });
+        if let Err(error) = copied {
+            unsafe {
+                (self.xlib.XDestroyWindow)(self.display, window);
+            }
+            return Err(error);
+        }
+
+        unsafe {
+            let marker: c_ulong = child;
+            (self.xlib.XChangeProperty)(
+                self.display,
+                window,
+                self.parent_atom,
+                XA_WINDOW,
+                32,
+                PropModeReplace,
+                &marker as *const c_ulong as *const u8,
+                1,
             );
 
+            if let Some(transient_for) = transient_for {
+                (self.xlib.XSetTransientForHint)(self.display, window, transient_for);
+            }
             (self.xlib.XMapWindow)(self.display, window);
 
+            // the parent accepts input itself and then passes it on, so the
+            // window manager can focus it like any other window
+            let mut hints: XWMHints = mem::zeroed();
+            hints.flags = InputHint;
+            hints.input = True;
+            (self.xlib.XSetWMHints)(self.display, window, &mut hints);
+
             // setup receiving the close and resize messages from the wm
             let mut atom_list = [self.take_focus_atom, self.close_window_atom];
             let atom_list_len = atom_list.len() as i32;
             if (self.xlib.XSetWMProtocols)(self.display, window, atom_list.as_mut_ptr(), atom_list_len) == 0 {
-                panic!("could not set protocols");
+                (self.xlib.XDestroyWindow)(self.display, window);
+                return Err(Error::MissingAtom { name: "WM_PROTOCOLS" });
             }
-
-            window
         }
+
+        self.created_windows.insert(window);
+        Ok(window)
     }
 
-    // sends a request for the child to close, and then calls 
-    // destroy window itself
-    pub fn notify_child_should_close(&self, child: WindowHandle, parent: WindowHandle) {
-        unsafe {
-            let root = (self.xlib.XRootWindow)(self.display, self.default_screen());
-            (self.xlib.XUnmapWindow)(self.display, child);
-            (self.xlib.XSync)(self.display, False);
+    // a plain window that takes the keyboard input itself, for
+    // looking at the keys that are pressed in it
+    pub fn create_input_window(&mut self, title: &str) -> Result<WindowHandle, Error> {
+        let screen = self.default_screen();
+        let title = CString::new(title).unwrap_or_default();
 
-            (self.xlib.XReparentWindow)(self.display, child, root, 0, 0);
+        self.checked(|| unsafe {
+            let root = (self.xlib.XRootWindow)(self.display, screen);
+            let black = (self.xlib.XBlackPixel)(self.display, screen);
+            let white = (self.xlib.XWhitePixel)(self.display, screen);
+            let window =
+                (self.xlib.XCreateSimpleWindow)(self.display, root, 0, 0, 400, 200, 0, black, white);
 
-            // allow time for the XServer to receive the
-            // events before syncing
-            // thread::sleep(Duration::from_millis(1));
-            (self.xlib.XSync)(self.display, False);
+            (self.xlib.XSelectInput)(
+                self.display,
+                window,
+                KeyPressMask | KeyReleaseMask | StructureNotifyMask,
+            );
+            (self.xlib.XStoreName)(self.display, window, title.as_ptr());
 
-            (self.xlib.XDestroyWindow)(self.display, parent);
-        } 
+            let mut atom_list = [self.close_window_atom];
+            (self.xlib.XSetWMProtocols)(self.display, window, atom_list.as_mut_ptr(), 1);
+            (self.xlib.XMapWindow)(self.display, window);
 
-        let mut client_data = [0; 10];
-        client_data[0] = self.close_window_atom as i32;
-        client_data[1] = CurrentTime as i32;
-        
-        unsafe {
-            // turn it into the format i can pass to the struct
-            let client_data = mem::transmute::<[i32; 10], ClientMessageData>(client_data);
+            window
+        })
+    }
 
-            let mut event = XClientMessageEvent {
-                type_: ClientMessage,
-                display: self.display,
-                send_event: True,
-                serial: 0,
-                window: child,
-                message_type: self.wm_protocols_atom,
-                format: 32,
-                data: client_data
-            };
+    // the name of the keysym the key types with the keyboard layout that is
+    // used now, shift is the only modifier that changes it here
+    pub fn keysym_name(&self, key: Key) -> Option<String> {
+        if key.is_button() {
+            return None;
+        }
 
+        let level = (key.state & ShiftMask != 0) as i32;
+        let code = u8::try_from(key.code).ok()?;
 
-            let event_ptr = mem::transmute::<*mut XClientMessageEvent, *mut XEvent>(&mut event);
-            (self.xlib.XSendEvent)(self.display, child, False, NoEventMask, event_ptr);
+        unsafe {
+            let keysym = (self.xlib.XkbKeycodeToKeysym)(self.display, code, 0, level);
+            if keysym == 0 {
+                return None;
+            }
+
+            let name = (self.xlib.XKeysymToString)(keysym);
+            if name.is_null() {
+                return None;
+            }
+            Some(CStr::from_ptr(name).to_string_lossy().into_owned())
         }
     }
 
-    pub fn reparent_window(&self, child: WindowHandle, parent: WindowHandle) {
-        unsafe {
-            (self.xlib.XUnmapWindow)(self.display, child);
-            (self.xlib.XSync)(self.display, False);
-
-            (self.xlib.XReparentWindow)(self.display, child, parent, 0, 0);
-            (self.xlib.XMapWindow)(self.display, child);
+    pub fn is_created_window(&self, window: WindowHandle) -> bool {
+        self.created_windows.contains(&window)
+    }
 
-            // allow time for the XServer to receive the
-            // events before syncing
-            thread::sleep(Duration::from_millis(1));
-            (self.xlib.XSync)(self.display, False);
-        }
+    // starts sending PropertyChange events for the mirrored properties
+    // of the window
+    pub fn watch_properties(&self, window: WindowHandle) -> Result<(), Error> {
+        self.checked(|| unsafe {
+            (self.xlib.XSelectInput)(self.display, window, PropertyChangeMask);
+        })
     }
 
-    pub fn send_key_event(&self, window: Window, key: Key) {
-        let mut event = XKeyEvent {
-            type_: x11_dl::xlib::KeyPress,
-            display: self.display,
-            window,
-            time: CurrentTime,
-            same_screen: True,
-            send_event: True,
-            x: 1,
-            y: 1,
-            x_root: 1,
-            y_root: 1,
-            state: key.state,
-            keycode: key.code,
-            serial: 0,
-            root: 0,
-            subwindow: 0,
-        };
+    fn is_synced_property(&self, property: Atom) -> bool {
+        self.mirrored_atoms.contains(&property)
+            || Some(property) == self.wm_hints_atom
+            || Some(property) == self.wm_state_atom
+    }
 
-        unsafe {
-            // the library expects us to cast to *mut XEvent, with the data of XKeyEvent
-            let event_ptr = mem::transmute::<*mut XKeyEvent, *mut XEvent>(&mut event);
+    // called when a property of a child changed, its parent is
+    // updated to match
+    pub fn sync_child_property(
+        &self,
+        child: WindowHandle,
+        parent: WindowHandle,
+        property: AtomHandle,
+    ) -> Result<(), Error> {
+        self.checked(|| {
+            if self.mirrored_atoms.contains(&property) {
+                self.copy_property(child, parent, property);
+            } else if Some(property) == self.wm_hints_atom {
+                self.mirror_urgency(child, parent);
+            }
+        })
+    }
 
-            (self.xlib.XSendEvent)(self.display, window, False, KeyPressMask, event_ptr);
-            (self.xlib.XFlush)(self.display);
-        }
+    // called when a property of a parent changed. The window manager sets
+    // the state on the parent, which the child has to know about to draw
+    // itself fullscreen or maximized
+    pub fn sync_parent_property(
+        &self,
+        parent: WindowHandle,
+        child: WindowHandle,
+        property: AtomHandle,
+    ) -> Result<(), Error> {
+        self.checked(|| {
+            if Some(property) == self.wm_state_atom {
+                self.copy_property(parent, child, property);
+            }
+        })
     }
 
-    pub fn get_window_class(&mut self, window: Window) -> Option<CString> {
+    // the parent has its own WM_HINTS so that it can take input, so only
+    // the urgency of the child's hints is copied over
+    fn mirror_urgency(&self, child: WindowHandle, parent: WindowHandle) {
         unsafe {
-            let mut class_hint: MaybeUninit<XClassHint> = MaybeUninit::uninit();
-            let status = (self.xlib.XGetClassHint)(self.display, window, class_hint.as_mut_ptr());
+            let child_hints = (self.xlib.XGetWMHints)(self.display, child);
+            let urgent = !child_hints.is_null() && (*child_hints).flags & XUrgencyHint != 0;
+            if !child_hints.is_null() {
+                (self.xlib.XFree)(child_hints as *mut c_void);
+            }
 
-            // if it succeeded, then, return back the string
-            if status != 0 {
-                (self.xlib.XFree)(class_hint.assume_init().res_name as *mut c_void);
-                Some(CString::from_raw(class_hint.assume_init().res_class))
-            } else {
-                None
+            let parent_hints = (self.xlib.XGetWMHints)(self.display, parent);
+            if parent_hints.is_null() {
+                return;
+            }
+
+            let was_urgent = (*parent_hints).flags & XUrgencyHint != 0;
+            if urgent != was_urgent {
+                (*parent_hints).flags ^= XUrgencyHint;
+                (self.xlib.XSetWMHints)(self.display, parent, parent_hints);
             }
+            (self.xlib.XFree)(parent_hints as *mut c_void);
         }
     }
 
-    pub fn get_window_pid(&mut self, window: Window) -> Option<u32> {
-        let atom = self.pid_atom?;
+    // sends a _NET_WM_STATE request on to the window manager, as if it
+    // had been made by the parent
+    pub fn forward_state_request(&self, parent: WindowHandle, data: [i64; 5]) -> Result<(), Error> {
+        let wm_state = match self.wm_state_atom {
+            Some(atom) => atom,
+            None => return Ok(()),
+        };
 
-        let mut _actual_type = 0;
-        let mut _actual_format = 0;
-        let mut _num_items = 0;
-        let mut _bytes_after = 0;
+        let mut message = ClientMessageData::new();
+        for (i, value) in data.into_iter().enumerate() {
+            message.set_long(i, value as c_long);
+        }
 
-        unsafe {
-            let mut prop = ptr::null::<u8>() as *mut u8;
-            // we do not need any of the other data, as prop is the only
-            // one we want. In this case prop will be set to the value of
-            // the id, when casted to an u32
-            (self.xlib.XGetWindowProperty)(
+        self.queued(|| unsafe {
+            let root = self.root_of(parent);
+            let mut event = XClientMessageEvent {
+                type_: ClientMessage,
+                display: self.display,
+                send_event: True,
+                serial: 0,
+                window: parent,
+                message_type: wm_state,
+                format: 32,
+                data: message,
+            };
+
+            let event_ptr = mem::transmute::<*mut XClientMessageEvent, *mut XEvent>(&mut event);
+            (self.xlib.XSendEvent)(
                 self.display,
-                window,
-                atom,
-                0,
-                20,
+                root,
+                False,
+                SubstructureRedirectMask | SubstructureNotifyMask,
+                event_ptr,
+            );
+        })
+    }
+
+    pub fn destroy_window(&mut self, window: WindowHandle) -> Result<(), Error> {
+        self.created_windows.remove(&window);
+        self.paused_windows.remove(&window);
+        let keys = self.grabbed_keys.remove(&window);
+
+        self.checked(|| unsafe {
+            if let Some(keys) = &keys {
+                ungrab_keys(&self.xlib, self.display, window, keys);
+            }
+            (self.xlib.XDestroyWindow)(self.display, window);
+        })
+    }
+
+    pub fn map_window(&self, window: WindowHandle) -> Result<(), Error> {
+        self.queued(|| unsafe {
+            (self.xlib.XMapWindow)(self.display, window);
+        })
+    }
+
+    // tells the child its real geometry after one of its configure requests,
+    // which is what ICCCM asks for when a request is not granted as it was
+    pub fn confirm_configure(&self, child: WindowHandle) -> Result<(), Error> {
+        self.queued(|| unsafe {
+            let mut attributes: MaybeUninit<XWindowAttributes> = MaybeUninit::uninit();
+            if (self.xlib.XGetWindowAttributes)(self.display, child, attributes.as_mut_ptr()) == 0 {
+                return;
+            }
+            let attributes = attributes.assume_init();
+
+            // synthetic configure events are in root coordinates
+            let mut x = 0;
+            let mut y = 0;
+            let mut _child = 0;
+            (self.xlib.XTranslateCoordinates)(
+                self.display,
+                child,
+                attributes.root,
+                0,
+                0,
+                &mut x,
+                &mut y,
+                &mut _child,
+            );
+
+            let mut event = XConfigureEvent {
+                type_: x11_dl::xlib::ConfigureNotify,
+                serial: 0,
+                send_event: True,
+                display: self.display,
+                event: child,
+                window: child,
+                x,
+                y,
+                width: attributes.width,
+                height: attributes.height,
+                border_width: attributes.border_width,
+                above: 0,
+                override_redirect: False,
+            };
+
+            let event_ptr = mem::transmute::<*mut XConfigureEvent, *mut XEvent>(&mut event);
+            (self.xlib.XSendEvent)(self.display, child, False, StructureNotifyMask, event_ptr);
+        })
+    }
+
+    fn root_of(&self, window: WindowHandle) -> WindowHandle {
+        unsafe {
+            let mut attributes: MaybeUninit<XWindowAttributes> = MaybeUninit::uninit();
+            if (self.xlib.XGetWindowAttributes)(self.display, window, attributes.as_mut_ptr()) == 0 {
+                return (self.xlib.XDefaultRootWindow)(self.display);
+            }
+            attributes.assume_init().root
+        }
+    }
+
+    pub fn mirror_properties(&self, from: WindowHandle, to: WindowHandle) -> Result<(), Error> {
+        self.checked(|| self.copy_properties(from, to))
+    }
+
+    fn copy_properties(&self, from: WindowHandle, to: WindowHandle) {
+        for &property in &self.mirrored_atoms {
+            self.copy_property(from, to, property);
+        }
+    }
+
+    // the title of a paused parent has PAUSED_SUFFIX after the title of its
+    // child, so it can be seen in the task bar that the keys are not remapped
+    pub fn set_paused(&mut self, parent: WindowHandle, child: WindowHandle, paused: bool) -> Result<(), Error> {
+        let changed = match paused {
+            true => self.paused_windows.insert(parent),
+            false => self.paused_windows.remove(&parent),
+        };
+        if !changed {
+            return Ok(());
+        }
+
+        self.checked(|| {
+            for &property in &self.title_atoms {
+                self.copy_property(child, parent, property);
+            }
+        })
+    }
+
+    // copies the property as it is, or deletes it from to when from
+    // does not have it anymore
+    fn copy_property(&self, from: WindowHandle, to: WindowHandle, property: AtomHandle) {
+        let mut actual_type = 0;
+        let mut actual_format = 0;
+        let mut num_items = 0;
+        let mut _bytes_after = 0;
+
+        unsafe {
+            let mut prop = ptr::null_mut::<u8>();
+            (self.xlib.XGetWindowProperty)(
+                self.display,
+                from,
+                property,
+                0,
+                // the length is in 32 bit units, this is big enough
+                // for even the largest of icons
+                0x7fff_ffff,
+                False,
+                AnyPropertyType as u64,
+                &mut actual_type,
+                &mut actual_format,
+                &mut num_items,
+                &mut _bytes_after,
+                &mut prop,
+            );
+
+            let paused_title = actual_format == 8
+                && self.paused_windows.contains(&to)
+                && self.title_atoms.contains(&property);
+
+            if actual_type == 0 {
+                (self.xlib.XDeleteProperty)(self.display, to, property);
+            } else if paused_title {
+                let mut title = match prop.is_null() {
+                    true => Vec::new(),
+                    false => std::slice::from_raw_parts(prop, num_items as usize).to_vec(),
+                };
+                title.extend_from_slice(PAUSED_SUFFIX.as_bytes());
+                (self.xlib.XChangeProperty)(
+                    self.display,
+                    to,
+                    property,
+                    actual_type,
+                    actual_format,
+                    PropModeReplace,
+                    title.as_ptr(),
+                    title.len() as i32,
+                );
+            } else {
+                (self.xlib.XChangeProperty)(
+                    self.display,
+                    to,
+                    property,
+                    actual_type,
+                    actual_format,
+                    PropModeReplace,
+                    prop,
+                    num_items as i32,
+                );
+            }
+
+            if !prop.is_null() {
+                (self.xlib.XFree)(prop as *mut c_void);
+            }
+        }
+    }
+
+    // sends a request for the child to close, and then calls 
+    // destroy window itself
+    pub fn notify_child_should_close(&mut self, child: WindowHandle, parent: WindowHandle) -> Result<(), Error> {
+        let moved = self.checked(|| unsafe {
+            let root = self.root_of(parent);
+            (self.xlib.XUnmapWindow)(self.display, child);
+            (self.xlib.XSync)(self.display, False);
+
+            (self.xlib.XReparentWindow)(self.display, child, root, 0, 0);
+        });
+
+        // the parent is destroyed even when the child is already gone
+        self.destroy_window(parent)?;
+        moved?;
+
+        self.queued(|| self.send_protocol_message(child, self.close_window_atom, CurrentTime))
+    }
+
+    // gives the child back to the window manager where the parent was, and
+    // destroys the parent. The child is left as it was before it was wrapped
+    pub fn release_window(&mut self, child: WindowHandle, parent: WindowHandle) -> Result<(), Error> {
+        let moved = self.checked(|| unsafe {
+            let root = self.root_of(parent);
+            let mut x = 0;
+            let mut y = 0;
+            let mut unused_child = 0;
+            (self.xlib.XTranslateCoordinates)(
+                self.display,
+                parent,
+                root,
+                0,
+                0,
+                &mut x,
+                &mut y,
+                &mut unused_child,
+            );
+
+            (self.xlib.XUnmapWindow)(self.display, child);
+            (self.xlib.XReparentWindow)(self.display, child, root, x, y);
+            (self.xlib.XMapWindow)(self.display, child);
+        });
+
+        self.destroy_window(parent)?;
+        moved
+    }
+
+    // sends one of the WM_PROTOCOLS messages, the same way a window
+    // manager would send it
+    fn send_protocol_message(&self, window: WindowHandle, protocol: Atom, time: Time) {
+        let mut client_data = [0; 5];
+        client_data[0] = protocol as c_long;
+        client_data[1] = time as c_long;
+
+        let mut data = ClientMessageData::new();
+        for (i, value) in client_data.into_iter().enumerate() {
+            data.set_long(i, value);
+        }
+
+        unsafe {
+            let mut event = XClientMessageEvent {
+                type_: ClientMessage,
+                display: self.display,
+                send_event: True,
+                serial: 0,
+                window,
+                message_type: self.wm_protocols_atom,
+                format: 32,
+                data,
+            };
+
+            let event_ptr = mem::transmute::<*mut XClientMessageEvent, *mut XEvent>(&mut event);
+            (self.xlib.XSendEvent)(self.display, window, False, NoEventMask, event_ptr);
+        }
+    }
+
+    pub fn reparent_window(&self, child: WindowHandle, parent: WindowHandle) -> Result<(), Error> {
+        self.checked(|| unsafe {
+            (self.xlib.XUnmapWindow)(self.display, child);
+            (self.xlib.XSync)(self.display, False);
+
+            (self.xlib.XReparentWindow)(self.display, child, parent, 0, 0);
+            (self.xlib.XMapWindow)(self.display, child);
+
+            // allow time for the XServer to receive the
+            // events before syncing
+            thread::sleep(Duration::from_millis(1));
+        })
+    }
+
+    pub fn send_key_event(&self, window: Window, key: Key, pressed: bool) -> Result<(), Error> {
+        let (type_, event_mask) = match pressed {
+            true => (x11_dl::xlib::KeyPress, KeyPressMask),
+            false => (x11_dl::xlib::KeyRelease, KeyReleaseMask),
+        };
+        let mut event = XKeyEvent {
+            type_,
+            display: self.display,
+            window,
+            time: CurrentTime,
+            same_screen: True,
+            send_event: True,
+            x: 1,
+            y: 1,
+            x_root: 1,
+            y_root: 1,
+            state: key.modifiers(),
+            keycode: key.code,
+            serial: 0,
+            root: 0,
+            subwindow: 0,
+        };
+
+        self.queued(|| unsafe {
+            // the library expects us to cast to *mut XEvent, with the data of XKeyEvent
+            let event_ptr = mem::transmute::<*mut XKeyEvent, *mut XEvent>(&mut event);
+
+            (self.xlib.XSendEvent)(self.display, window, False, event_mask, event_ptr);
+        })
+    }
+
+    // the button is sent to the deepest window the pointer is in inside
+    // the child, where the child would have seen it if the parent had not
+    // grabbed it. Toolkits with subwindows only look at events on those
+    pub fn send_button_event(&self, window: Window, button: Key, pressed: bool) -> Result<(), Error> {
+        let mut window = window;
+        let mut root = 0;
+        let (mut x_root, mut y_root, mut x, mut y) = (0, 0, 0, 0);
+        self.checked(|| unsafe {
+            loop {
+                let mut subwindow = 0;
+                let mut mask = 0;
+                let on_screen = (self.xlib.XQueryPointer)(
+                    self.display,
+                    window,
+                    &mut root,
+                    &mut subwindow,
+                    &mut x_root,
+                    &mut y_root,
+                    &mut x,
+                    &mut y,
+                    &mut mask,
+                );
+                if on_screen == 0 || subwindow == 0 {
+                    break;
+                }
+                window = subwindow;
+            }
+        })?;
+
+        let (type_, event_mask) = match pressed {
+            true => (x11_dl::xlib::ButtonPress, ButtonPressMask),
+            false => (x11_dl::xlib::ButtonRelease, ButtonReleaseMask),
+        };
+        let mut event = XButtonEvent {
+            type_,
+            serial: 0,
+            send_event: True,
+            display: self.display,
+            window,
+            root,
+            subwindow: 0,
+            time: CurrentTime,
+            x,
+            y,
+            x_root,
+            y_root,
+            state: button.modifiers(),
+            button: button.code,
+            same_screen: True,
+        };
+
+        self.queued(|| unsafe {
+            let event_ptr = &mut event as *mut XButtonEvent as *mut XEvent;
+            (self.xlib.XSendEvent)(self.display, window, False, event_mask, event_ptr);
+        })
+    }
+
+    // types the text into the window, see typing::type_text. A character
+    // that is not on the keyboard is put on a keycode that has nothing on
+    // it, which is given back by restore_keycodes once the window had time
+    // to look it up
+    pub fn type_text(&mut self, window: Window, text: &str) -> Result<(), Error> {
+        typing::type_text(&mut TypingInto { x: self, window }, text)
+    }
+
+    // the key that types the keysym without AltGr or anything else
+    // that is not the same on every keyboard
+    fn keysym_key(&self, keysym: KeySym) -> Option<Key> {
+        if let Some(borrowed) = self.borrowed_keycodes.iter().find(|borrowed| borrowed.keysym == keysym) {
+            return Some(Key {
+                code: borrowed.keycode as u32,
+                state: 0,
+            });
+        }
+
+        unsafe {
+            let keycode = (self.xlib.XKeysymToKeycode)(self.display, keysym);
+            if keycode == 0 {
+                return None;
+            }
+
+            (0..2)
+                .find(|&level| (self.xlib.XkbKeycodeToKeysym)(self.display, keycode, 0, level) == keysym)
+                .map(|level| Key {
+                    code: keycode as u32,
+                    state: if level == 1 { ShiftMask } else { 0 },
+                })
+        }
+    }
+
+    fn borrow_keycode(&mut self, keysym: KeySym) -> Result<Option<Key>, Error> {
+        let keycode = match self.spare_keycode()? {
+            Some(keycode) => keycode,
+            // every spare keycode is in use, so the one that was borrowed
+            // the longest ago is taken over. The events typed with it have
+            // to reach the server before it is given another keysym
+            None if !self.borrowed_keycodes.is_empty() => {
+                unsafe {
+                    (self.xlib.XSync)(self.display, False);
+                }
+                self.borrowed_keycodes.remove(0).keycode
+            }
+            None => return Ok(None),
+        };
+
+        let mut keysyms = [keysym, keysym];
+        self.checked(|| unsafe {
+            (self.xlib.XChangeKeyboardMapping)(self.display, keycode as c_int, 2, keysyms.as_mut_ptr(), 1);
+        })?;
+
+        self.borrowed_keycodes.push(BorrowedKeycode {
+            keycode,
+            keysym,
+            since: Instant::now(),
+        });
+        Ok(Some(Key {
+            code: keycode as u32,
+            state: 0,
+        }))
+    }
+
+    // a keycode the server knows of that has no keysyms at all
+    fn spare_keycode(&self) -> Result<Option<u8>, Error> {
+        let (mut min, mut max) = (0, 0);
+        let mut per_keycode = 0;
+        let keysyms = self.checked(|| unsafe {
+            (self.xlib.XDisplayKeycodes)(self.display, &mut min, &mut max);
+            (self.xlib.XGetKeyboardMapping)(self.display, min as u8, max - min + 1, &mut per_keycode)
+        })?;
+        if keysyms.is_null() {
+            return Ok(None);
+        }
+
+        let per_keycode = per_keycode as usize;
+        let count = (max - min + 1) as usize;
+        let spare = unsafe {
+            let keysyms = std::slice::from_raw_parts(keysyms, count * per_keycode);
+            // from the top, where there are rarely any keys
+            let spare = (0..count)
+                .rev()
+                .map(|i| (min as usize + i) as u8)
+                .zip(keysyms.chunks(per_keycode).rev())
+                .find(|(keycode, keysyms)| {
+                    keysyms.iter().all(|&keysym| keysym == 0)
+                        && !self.borrowed_keycodes.iter().any(|borrowed| borrowed.keycode == *keycode)
+                })
+                .map(|(keycode, _)| keycode);
+            (self.xlib.XFree)(keysyms.as_ptr() as *mut c_void);
+            spare
+        };
+
+        Ok(spare)
+    }
+
+    // empties the keycodes that were borrowed at least `after` ago
+    pub fn restore_keycodes(&mut self, after: Duration) -> Result<(), Error> {
+        let (restored, kept) = self
+            .borrowed_keycodes
+            .drain(..)
+            .partition(|borrowed| borrowed.since.elapsed() >= after);
+        self.borrowed_keycodes = kept;
+
+        let restored: Vec<BorrowedKeycode> = restored;
+        if restored.is_empty() {
+            return Ok(());
+        }
+
+        self.checked(|| unsafe {
+            for borrowed in &restored {
+                let mut keysyms = [0, 0];
+                (self.xlib.XChangeKeyboardMapping)(
+                    self.display,
+                    borrowed.keycode as c_int,
+                    2,
+                    keysyms.as_mut_ptr(),
+                    1,
+                );
+            }
+        })
+    }
+
+    // the key or button that pastes the selection in most programs, ctrl+v
+    // for the clipboard and the middle button for the primary selection
+    pub fn paste_key(&self, selection: Selection) -> Option<Key> {
+        match selection {
+            Selection::Clipboard => self.keysym_key(XK_v as KeySym).map(|key| Key {
+                code: key.code,
+                state: key.state | ControlMask,
+            }),
+            Selection::Primary => Some(Key {
+                code: 2,
+                state: MOUSE_BUTTON,
+            }),
+        }
+    }
+
+    fn selection_atom(&self, selection: Selection) -> Atom {
+        match selection {
+            Selection::Clipboard => self.clipboard_atom,
+            Selection::Primary => XA_PRIMARY,
+        }
+    }
+
+    fn selection_window(&mut self) -> Result<Window, Error> {
+        if let Some(window) = self.selection_window {
+            return Ok(window);
+        }
+
+        let screen = self.default_screen();
+        let window = self.checked(|| unsafe {
+            let root = (self.xlib.XRootWindow)(self.display, screen);
+            let window = (self.xlib.XCreateSimpleWindow)(self.display, root, 0, 0, 1, 1, 0, 0, 0);
+            (self.xlib.XSelectInput)(self.display, window, PropertyChangeMask);
+            window
+        })?;
+
+        self.selection_window = Some(window);
+        Ok(window)
+    }
+
+    // asks the owner of the selection for its text, which arrives through
+    // handle_selection_event once the owner has converted it. Time is the
+    // time of the key press the selection is read for
+    pub fn request_selection(&mut self, selection: Selection, time: Time) -> Result<(), Error> {
+        let selection = self.selection_atom(selection);
+
+        // a paste right after another one reads the text from before it
+        self.restore_selection(selection)?;
+
+        self.transfer = Some(SelectionTransfer {
+            selection,
+            target: self.utf8_atom,
+            time,
+            incr: false,
+            data: Vec::new(),
+            since: Instant::now(),
+        });
+        self.convert_selection(selection, self.utf8_atom, time)
+    }
+
+    fn convert_selection(&mut self, selection: Atom, target: Atom, time: Time) -> Result<(), Error> {
+        let window = self.selection_window()?;
+        self.checked(|| unsafe {
+            (self.xlib.XConvertSelection)(self.display, selection, target, self.transfer_atom, window, time);
+        })
+    }
+
+    // returns the text of the selection once all of it was read. A
+    // selection without an owner or one that can not be converted to
+    // text is read as empty text
+    pub fn handle_selection_event(&mut self, event: SelectionEvent) -> Result<Option<String>, Error> {
+        match event {
+            SelectionEvent::Notify { property } => self.receive_selection(property),
+            SelectionEvent::NewValue => self.receive_chunk(),
+            SelectionEvent::Request {
+                requestor,
+                selection,
+                target,
+                property,
+                time,
+            } => {
+                self.answer_selection_request(requestor, selection, target, property, time)?;
+                Ok(None)
+            }
+            SelectionEvent::PropertyDelete { requestor, property } => {
+                self.send_chunk(requestor, property)?;
+                Ok(None)
+            }
+            SelectionEvent::Clear { selection } => {
+                self.owned_selections.remove(&selection);
+                Ok(None)
+            }
+        }
+    }
+
+    fn receive_selection(&mut self, property: Option<Atom>) -> Result<Option<String>, Error> {
+        let mut transfer = match self.transfer.take() {
+            Some(transfer) if !transfer.incr => transfer,
+            // nothing is waiting for it
+            other => {
+                self.transfer = other;
+                return Ok(None);
+            }
+        };
+
+        let property = match property {
+            Some(property) => property,
+            // owners that only know the older STRING are asked for that
+            None if transfer.target == self.utf8_atom => {
+                transfer.target = XA_STRING;
+                let (selection, time) = (transfer.selection, transfer.time);
+                self.transfer = Some(transfer);
+                self.convert_selection(selection, XA_STRING, time)?;
+                return Ok(None);
+            }
+            None => return Ok(Some(String::new())),
+        };
+
+        let window = self.selection_window()?;
+        let (kind, data) = self.take_property(window, property)?;
+
+        // deleting the INCR property tells the owner to send the first chunk
+        if kind == self.incr_atom {
+            transfer.incr = true;
+            self.transfer = Some(transfer);
+            return Ok(None);
+        }
+
+        Ok(Some(decode_text(transfer.target, &data)))
+    }
+
+    fn receive_chunk(&mut self) -> Result<Option<String>, Error> {
+        let mut transfer = match self.transfer.take() {
+            Some(transfer) if transfer.incr => transfer,
+            other => {
+                self.transfer = other;
+                return Ok(None);
+            }
+        };
+
+        let window = self.selection_window()?;
+        let (_, chunk) = self.take_property(window, self.transfer_atom)?;
+        if chunk.is_empty() {
+            return Ok(Some(decode_text(transfer.target, &transfer.data)));
+        }
+
+        transfer.data.extend(chunk);
+        self.transfer = Some(transfer);
+        Ok(None)
+    }
+
+    // reads and deletes a property of 8 bit items, returning its type
+    fn take_property(&self, window: Window, property: Atom) -> Result<(Atom, Vec<u8>), Error> {
+        let mut actual_type = 0;
+        let mut actual_format = 0;
+        let mut num_items = 0;
+        let mut _bytes_after = 0;
+        let mut prop = ptr::null_mut::<u8>();
+
+        self.checked(|| unsafe {
+            (self.xlib.XGetWindowProperty)(
+                self.display,
+                window,
+                property,
+                0,
+                MAX_PROPERTY_LENGTH,
+                True,
+                AnyPropertyType as u64,
+                &mut actual_type,
+                &mut actual_format,
+                &mut num_items,
+                &mut _bytes_after,
+                &mut prop,
+            );
+        })?;
+
+        if prop.is_null() {
+            return Ok((actual_type, Vec::new()));
+        }
+        let data = unsafe {
+            let data = match actual_format {
+                8 => std::slice::from_raw_parts(prop, num_items as usize).to_vec(),
+                _ => Vec::new(),
+            };
+            (self.xlib.XFree)(prop as *mut c_void);
+            data
+        };
+
+        Ok((actual_type, data))
+    }
+
+    // makes the selection window the owner of the selection, so that the
+    // window the text is pasted in gets it from us. Previous is the text
+    // the selection had, which it gets back once the text was pasted.
+    // ICCCM asks for the time of the event that caused the change
+    pub fn own_selection(
+        &mut self,
+        selection: Selection,
+        text: String,
+        previous: String,
+        time: Time,
+    ) -> Result<bool, Error> {
+        let window = self.selection_window()?;
+        let selection = self.selection_atom(selection);
+        let owner = self.checked(|| unsafe {
+            (self.xlib.XSetSelectionOwner)(self.display, selection, window, time);
+            (self.xlib.XGetSelectionOwner)(self.display, selection)
+        })?;
+
+        if owner != window {
+            return Ok(false);
+        }
+        self.owned_selections.insert(
+            selection,
+            OwnedSelection {
+                text,
+                restore: Some(previous),
+                pasted: None,
+                time,
+                since: Instant::now(),
+            },
+        );
+        Ok(true)
+    }
+
+    // the paste key was sent to the window, which is the one the text is
+    // for
+    pub fn selection_pasted(&mut self, selection: Selection, window: WindowHandle) {
+        let selection = self.selection_atom(selection);
+        if let Some(owned) = self.owned_selections.get_mut(&selection) {
+            owned.pasted = Some((window, Instant::now()));
+        }
+    }
+
+    // restores the selection once the window it was pasted into, or one
+    // of its subwindows, got the text
+    fn served_selection(&mut self, selection: Atom, requestor: Window) -> Result<(), Error> {
+        let pasted = match self.owned_selections.get(&selection).and_then(|owned| owned.pasted) {
+            Some((window, _)) => window,
+            None => return Ok(()),
+        };
+
+        if self.checked(|| focus::is_within(self, requestor, pasted))? {
+            self.restore_selection(selection)?;
+        }
+        Ok(())
+    }
+
+    // a selection that was changed for a paste gets the text it had before
+    // back, so pasting does not change what was copied. Only text can be
+    // given back, a selection that had none before is given up
+    fn restore_selection(&mut self, selection: Atom) -> Result<(), Error> {
+        let owned = match self.owned_selections.get_mut(&selection) {
+            Some(owned) => owned,
+            None => return Ok(()),
+        };
+
+        match owned.restore.take() {
+            None => Ok(()),
+            Some(text) if !text.is_empty() => {
+                owned.text = text;
+                Ok(())
+            }
+            Some(_) => {
+                let time = owned.time;
+                self.owned_selections.remove(&selection);
+                self.queued(|| unsafe {
+                    (self.xlib.XSetSelectionOwner)(self.display, selection, 0, time);
+                })
+            }
+        }
+    }
+
+    // answers with the text as it was converted to the target. Text that
+    // fits in a single request is stored on the requestor at once, larger
+    // text is sent with INCR. The selection is restored once the window it
+    // was pasted into got its text. A request from before the selection
+    // was owned is refused, as ICCCM 2.2 asks
+    fn answer_selection_request(
+        &mut self,
+        requestor: Window,
+        selection: Atom,
+        target: Atom,
+        property: Atom,
+        time: Time,
+    ) -> Result<(), Error> {
+        // obsolete clients leave the property out and expect the target
+        // to be used as the property instead
+        let property = if property == 0 { target } else { property };
+
+        let owned = self
+            .owned_selections
+            .get(&selection)
+            .filter(|owned| time == CurrentTime || time >= owned.time);
+        let owned_now = owned.is_some();
+        let text = match owned {
+            Some(owned) if target == self.utf8_atom => Some(owned.text.as_bytes().to_vec()),
+            Some(owned) if target == XA_STRING => Some(encode_latin1(&owned.text)),
+            _ => None,
+        };
+
+        // whether the text was stored in one piece
+        let mut served = false;
+        let stored = match text {
+            Some(data) if data.len() > self.max_property_size() => {
+                self.start_incr(requestor, property, selection, target, data)?;
+                true
+            }
+            Some(data) => {
+                served = true;
+                self.checked(|| unsafe {
+                    (self.xlib.XChangeProperty)(
+                        self.display,
+                        requestor,
+                        property,
+                        target,
+                        8,
+                        PropModeReplace,
+                        data.as_ptr(),
+                        data.len() as c_int,
+                    );
+                })?;
+                true
+            }
+            None if target == self.targets_atom && owned_now => {
+                let targets = [self.targets_atom, self.utf8_atom, XA_STRING];
+                self.checked(|| unsafe {
+                    (self.xlib.XChangeProperty)(
+                        self.display,
+                        requestor,
+                        property,
+                        XA_ATOM,
+                        32,
+                        PropModeReplace,
+                        targets.as_ptr() as *const u8,
+                        targets.len() as c_int,
+                    );
+                })?;
+                true
+            }
+            None => false,
+        };
+
+        let mut event = XSelectionEvent {
+            type_: x11_dl::xlib::SelectionNotify,
+            serial: 0,
+            send_event: True,
+            display: self.display,
+            requestor,
+            selection,
+            target,
+            property: if stored { property } else { 0 },
+            time,
+        };
+
+        self.queued(|| unsafe {
+            let event_ptr = &mut event as *mut XSelectionEvent as *mut XEvent;
+            (self.xlib.XSendEvent)(self.display, requestor, False, NoEventMask, event_ptr);
+        })?;
+
+        if served {
+            self.served_selection(selection, requestor)?;
+        }
+        Ok(())
+    }
+
+    // starts sending text that does not fit in a single request, as ICCCM
+    // 2.7.2 describes. The INCR property holds the size of the text, every
+    // time the requestor deletes the property the next chunk is stored,
+    // and an empty chunk ends the transfer. Seeing the deletes needs
+    // PropertyChangeMask on the requestor, which is added to the events
+    // we already select there and taken off again at the end
+    fn start_incr(
+        &mut self,
+        requestor: Window,
+        property: Atom,
+        selection: Atom,
+        target: Atom,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        let size = [data.len() as c_long];
+        let event_mask = self.checked(|| unsafe {
+            let mut attributes: MaybeUninit<XWindowAttributes> = MaybeUninit::uninit();
+            let event_mask = match (self.xlib.XGetWindowAttributes)(self.display, requestor, attributes.as_mut_ptr()) {
+                0 => NoEventMask,
+                _ => attributes.assume_init().your_event_mask,
+            };
+
+            (self.xlib.XSelectInput)(self.display, requestor, event_mask | PropertyChangeMask);
+            (self.xlib.XChangeProperty)(
+                self.display,
+                requestor,
+                property,
+                self.incr_atom,
+                32,
+                PropModeReplace,
+                size.as_ptr() as *const u8,
+                1,
+            );
+            event_mask
+        })?;
+
+        self.outgoing.insert(
+            (requestor, property),
+            OutgoingTransfer {
+                selection,
+                target,
+                data,
+                sent: 0,
+                event_mask,
+                since: Instant::now(),
+            },
+        );
+        Ok(())
+    }
+
+    // stores the next chunk of an INCR transfer once the requestor has
+    // deleted the last one
+    fn send_chunk(&mut self, requestor: Window, property: Atom) -> Result<(), Error> {
+        let (target, chunk) = match self.outgoing.get_mut(&(requestor, property)) {
+            Some(transfer) => {
+                let start = transfer.sent;
+                transfer.sent = (start + INCR_CHUNK_SIZE).min(transfer.data.len());
+                (transfer.target, transfer.data[start..transfer.sent].to_vec())
+            }
+            None => return Ok(()),
+        };
+
+        self.queued(|| unsafe {
+            (self.xlib.XChangeProperty)(
+                self.display,
+                requestor,
+                property,
+                target,
+                8,
+                PropModeReplace,
+                chunk.as_ptr(),
+                chunk.len() as c_int,
+            );
+        })?;
+
+        if chunk.is_empty() {
+            if let Some(transfer) = self.outgoing.remove(&(requestor, property)) {
+                self.end_incr(requestor, &transfer)?;
+                self.served_selection(transfer.selection, requestor)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end_incr(&self, requestor: Window, transfer: &OutgoingTransfer) -> Result<(), Error> {
+        self.queued(|| unsafe {
+            (self.xlib.XSelectInput)(self.display, requestor, transfer.event_mask);
+        })
+    }
+
+    // gives up on selection transfers that stalled, and gives the
+    // selections that were owned for a paste their text back, once the
+    // window they were pasted into had the grace period to ask for the
+    // text, or when the paste never happened
+    pub fn expire_selections(&mut self, timeout: Duration, grace: Duration) -> Result<(), Error> {
+        if self.transfer.as_ref().is_some_and(|transfer| transfer.since.elapsed() >= timeout) {
+            self.transfer = None;
+        }
+
+        let stalled: Vec<(Window, Atom)> = self
+            .outgoing
+            .iter()
+            .filter(|(_, transfer)| transfer.since.elapsed() >= timeout)
+            .map(|(&key, _)| key)
+            .collect();
+        for key in stalled {
+            if let Some(transfer) = self.outgoing.remove(&key) {
+                self.end_incr(key.0, &transfer)?;
+            }
+        }
+
+        let unpasted: Vec<Atom> = self
+            .owned_selections
+            .iter()
+            .filter(|(_, owned)| {
+                let grace_over = owned.pasted.is_some_and(|(_, since)| since.elapsed() >= grace);
+                owned.restore.is_some() && (grace_over || owned.since.elapsed() >= timeout)
+            })
+            .map(|(&selection, _)| selection)
+            .collect();
+        for selection in unpasted {
+            self.restore_selection(selection)?;
+        }
+
+        Ok(())
+    }
+
+    // the largest property a single request can store, with room left
+    // for the rest of the request
+    fn max_property_size(&self) -> usize {
+        let units = unsafe {
+            match (self.xlib.XExtendedMaxRequestSize)(self.display) {
+                0 => (self.xlib.XMaxRequestSize)(self.display),
+                units => units,
+            }
+        };
+
+        (units as usize * 4).saturating_sub(1024)
+    }
+
+    pub fn get_transient_for(&self, window: Window) -> Result<Option<WindowHandle>, Error> {
+        let mut transient_for = 0;
+        let status = self.checked(|| unsafe {
+            (self.xlib.XGetTransientForHint)(self.display, window, &mut transient_for)
+        })?;
+
+        if status != 0 && transient_for != 0 {
+            Ok(Some(transient_for))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_override_redirect(&self, window: Window) -> Result<bool, Error> {
+        self.checked(|| unsafe {
+            let mut attributes: MaybeUninit<XWindowAttributes> = MaybeUninit::uninit();
+            if (self.xlib.XGetWindowAttributes)(self.display, window, attributes.as_mut_ptr()) == 0 {
+                return false;
+            }
+            attributes.assume_init().override_redirect != 0
+        })
+    }
+
+    // the property is a list of types in order of preference, the
+    // first one that is known is used
+    pub fn get_window_type(&self, window: Window) -> Result<Option<WindowType>, Error> {
+        self.checked(|| self.read_window_type(window))
+    }
+
+    fn read_window_type(&self, window: Window) -> Option<WindowType> {
+        let atom = self.window_type_atom?;
+
+        let mut _actual_type = 0;
+        let mut actual_format = 0;
+        let mut num_items = 0;
+        let mut _bytes_after = 0;
+
+        unsafe {
+            let mut prop = ptr::null_mut::<u8>();
+            (self.xlib.XGetWindowProperty)(
+                self.display,
+                window,
+                atom,
+                0,
+                32,
+                False,
+                4, // XA_ATOM
+                &mut _actual_type,
+                &mut actual_format,
+                &mut num_items,
+                &mut _bytes_after,
+                &mut prop,
+            );
+
+            if prop.is_null() {
+                return None;
+            }
+
+            let mut window_type = None;
+            if actual_format == 32 {
+                let types = std::slice::from_raw_parts(prop as *const Atom, num_items as usize);
+                for &type_atom in types {
+                    let name = (self.xlib.XGetAtomName)(self.display, type_atom);
+                    if name.is_null() {
+                        continue;
+                    }
+
+                    window_type = CStr::from_ptr(name)
+                        .to_str()
+                        .ok()
+                        .and_then(WindowType::from_atom_name);
+                    (self.xlib.XFree)(name as *mut c_void);
+
+                    if window_type.is_some() {
+                        break;
+                    }
+                }
+            }
+
+            (self.xlib.XFree)(prop as *mut c_void);
+            window_type
+        }
+    }
+
+    pub fn get_window_class(&mut self, window: Window) -> Result<Option<CString>, Error> {
+        Ok(self.get_class_hint(window)?.map(|(_, class)| class))
+    }
+
+    // the first part of WM_CLASS, usually the name the program was run as
+    pub fn get_window_instance(&mut self, window: Window) -> Result<Option<CString>, Error> {
+        Ok(self.get_class_hint(window)?.map(|(instance, _)| instance))
+    }
+
+    fn get_class_hint(&self, window: Window) -> Result<Option<(CString, CString)>, Error> {
+        self.checked(|| unsafe {
+            let mut class_hint: MaybeUninit<XClassHint> = MaybeUninit::uninit();
+            let status = (self.xlib.XGetClassHint)(self.display, window, class_hint.as_mut_ptr());
+
+            // if it succeeded, then, return back the strings. They are
+            // copied, as xlib allocated them and has to free them
+            if status != 0 {
+                let hint = class_hint.assume_init();
+                let instance = CStr::from_ptr(hint.res_name).to_owned();
+                let class = CStr::from_ptr(hint.res_class).to_owned();
+                (self.xlib.XFree)(hint.res_name as *mut c_void);
+                (self.xlib.XFree)(hint.res_class as *mut c_void);
+                Some((instance, class))
+            } else {
+                None
+            }
+        })
+    }
+
+    // _NET_WM_NAME is utf8, WM_NAME is only used when it is not set
+    pub fn get_window_title(&mut self, window: Window) -> Result<Option<String>, Error> {
+        self.checked(|| {
+            for &atom in self.title_atoms.iter().rev() {
+                if let Some(title) = self.read_bytes(window, atom) {
+                    return Some(String::from_utf8_lossy(&title).into_owned());
+                }
+            }
+            None
+        })
+    }
+
+    fn read_bytes(&self, window: Window, property: Atom) -> Option<Vec<u8>> {
+        let mut actual_type = 0;
+        let mut actual_format = 0;
+        let mut num_items = 0;
+        let mut _bytes_after = 0;
+
+        unsafe {
+            let mut prop = ptr::null_mut::<u8>();
+            (self.xlib.XGetWindowProperty)(
+                self.display,
+                window,
+                property,
+                0,
+                1024,
+                False,
+                AnyPropertyType as u64,
+                &mut actual_type,
+                &mut actual_format,
+                &mut num_items,
+                &mut _bytes_after,
+                &mut prop,
+            );
+
+            if prop.is_null() {
+                return None;
+            }
+            let bytes = match actual_format {
+                8 => Some(std::slice::from_raw_parts(prop, num_items as usize).to_vec()),
+                _ => None,
+            };
+            (self.xlib.XFree)(prop as *mut c_void);
+            bytes
+        }
+    }
+
+    // the windows of other clients, on every screen. The window manager
+    // lists them in _NET_CLIENT_LIST, without one they are found the way
+    // xprop finds them, by the WM_STATE the window manager sets on them.
+    // A parent of any remapper, this one or another, is listed as the
+    // child it wraps
+    pub fn client_windows(&mut self) -> Result<Vec<WindowHandle>, Error> {
+        self.checked(|| {
+            let mut windows = Vec::new();
+            for screen in 0..self.screen_count() {
+                let root = unsafe { (self.xlib.XRootWindow)(self.display, screen) };
+                match self.read_windows(root, self.client_list_atom) {
+                    Some(list) => windows.extend(list),
+                    None => {
+                        for window in self.query_children(root) {
+                            windows.extend(self.find_client(window, 2));
+                        }
+                    }
+                }
+            }
+
+            windows
+                .into_iter()
+                .map(|window| match self.read_windows(window, Some(self.parent_atom)).as_deref() {
+                    Some(&[child]) => child,
+                    _ => window,
+                })
+                .collect()
+        })
+    }
+
+    fn find_client(&self, window: Window, depth: u32) -> Option<Window> {
+        let wm_state = self.icccm_state_atom?;
+        if self.read_windows(window, Some(wm_state)).is_some() {
+            return Some(window);
+        }
+        if depth == 0 {
+            return None;
+        }
+
+        self.query_children(window)
+            .into_iter()
+            .find_map(|child| self.find_client(child, depth - 1))
+    }
+
+    fn query_children(&self, window: Window) -> Vec<Window> {
+        unsafe {
+            let mut root = 0;
+            let mut parent = 0;
+            let mut children = ptr::null_mut();
+            let mut child_count = 0;
+            let status = (self.xlib.XQueryTree)(
+                self.display,
+                window,
+                &mut root,
+                &mut parent,
+                &mut children,
+                &mut child_count,
+            );
+
+            let mut list = Vec::new();
+            if status != 0 && !children.is_null() {
+                list.extend_from_slice(std::slice::from_raw_parts(children, child_count as usize));
+            }
+            if !children.is_null() {
+                (self.xlib.XFree)(children as *mut c_void);
+            }
+            list
+        }
+    }
+
+    // reads a property of 32 bit values, which xlib gives back as longs
+    fn read_windows(&self, window: Window, property: Option<Atom>) -> Option<Vec<Window>> {
+        let property = property?;
+        let mut actual_type = 0;
+        let mut actual_format = 0;
+        let mut num_items = 0;
+        let mut _bytes_after = 0;
+
+        unsafe {
+            let mut prop = ptr::null_mut::<u8>();
+            (self.xlib.XGetWindowProperty)(
+                self.display,
+                window,
+                property,
+                0,
+                0x7fff_ffff,
+                False,
+                AnyPropertyType as u64,
+                &mut actual_type,
+                &mut actual_format,
+                &mut num_items,
+                &mut _bytes_after,
+                &mut prop,
+            );
+
+            if prop.is_null() {
+                return None;
+            }
+            let values = match actual_format {
+                32 => Some(std::slice::from_raw_parts(prop as *const c_ulong, num_items as usize).to_vec()),
+                _ => None,
+            };
+            (self.xlib.XFree)(prop as *mut c_void);
+            values
+        }
+    }
+
+    pub fn get_window_pid(&mut self, window: Window) -> Result<Option<u32>, Error> {
+        self.checked(|| self.read_window_pid(window))
+    }
+
+    fn read_window_pid(&self, window: Window) -> Option<u32> {
+        let atom = self.pid_atom?;
+
+        let mut _actual_type = 0;
+        let mut _actual_format = 0;
+        let mut _num_items = 0;
+        let mut _bytes_after = 0;
+
+        unsafe {
+            let mut prop = ptr::null::<u8>() as *mut u8;
+            // we do not need any of the other data, as prop is the only
+            // one we want. In this case prop will be set to the value of
+            // the id, when casted to an u32
+            (self.xlib.XGetWindowProperty)(
+                self.display,
+                window,
+                atom,
+                0,
+                20,
                 False,
                 6,
                 &mut _actual_type,
@@ -466,15 +2384,143 @@ impl XBridge {
 fn ungrab_keys(xlib: &Xlib, display: *mut Display, window: Window, key_map: &KeyMap) {
     for key in key_map.keys() {
         unsafe {
-            (xlib.XUngrabKey)(display, key.code.try_into().unwrap(), key.state, window);
+            match key.is_button() {
+                true => (xlib.XUngrabButton)(display, key.code, grab_state(*key), window),
+                false => (xlib.XUngrabKey)(display, key.code.try_into().unwrap(), grab_state(*key), window),
+            };
         }
     }
 }
 
+// the keysym that types a character. Latin-1 keysyms are the character
+// itself, the rest of unicode is offset by 0x01000000
+fn char_keysym(c: char) -> Option<KeySym> {
+    match c {
+        '\n' => Some(0xff0d),
+        '\t' => Some(0xff09),
+        ' '..='~' | '\u{a0}'..='\u{ff}' => Some(c as KeySym),
+        c if c.is_control() => None,
+        c => Some(0x0100_0000 + c as KeySym),
+    }
+}
+
+// a wildcard has to see the key whatever modifiers are held, the
+// modifiers it needs are checked once the key is pressed
+fn grab_state(key: Key) -> u32 {
+    match key.is_wildcard() {
+        true => AnyModifier,
+        false => key.modifiers(),
+    }
+}
+
 fn free_listen_window_creation(display: *mut Display, screen: i32) {}
 
+// STRING is latin-1, anything else is taken to be UTF8_STRING
+fn decode_text(target: Atom, data: &[u8]) -> String {
+    match target {
+        XA_STRING => data.iter().map(|&byte| byte as char).collect(),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    }
+}
+
+fn encode_latin1(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| u8::try_from(c as u32).unwrap_or(b'?'))
+        .collect()
+}
+
+// windows are at least a pixel in each direction, a size that is not
+// is taken as the smallest one instead of failing on it
+fn dimension(value: c_int) -> u32 {
+    value.max(1) as u32
+}
+
 fn intern_atom(xlib: &Xlib, display: *mut Display, atom_name: &'static str) -> Option<Atom> {
     let atom_name = CString::new(atom_name).unwrap();
     let atom = unsafe { (xlib.XInternAtom)(display, atom_name.as_ptr(), False) };
     if atom == 0 { None } else { Some(atom) }
 }
+
+impl FocusBackend for XBridge {
+    fn focus_model(&self, window: WindowHandle) -> FocusModel {
+        let input = unsafe {
+            let hints = (self.xlib.XGetWMHints)(self.display, window);
+            if hints.is_null() {
+                None
+            } else {
+                let input = if (*hints).flags & InputHint != 0 {
+                    Some((*hints).input != 0)
+                } else {
+                    None
+                };
+                (self.xlib.XFree)(hints as *mut c_void);
+                input
+            }
+        };
+
+        let takes_focus = self.supports_protocol(window, self.take_focus_atom);
+        FocusModel::from_hints(input, takes_focus)
+    }
+
+    fn set_input_focus(&self, window: WindowHandle, time: u64) {
+        unsafe {
+            (self.xlib.XSetInputFocus)(self.display, window, RevertToParent, time);
+        }
+    }
+
+    fn send_take_focus(&self, window: WindowHandle, time: u64) {
+        self.send_protocol_message(window, self.take_focus_atom, time);
+    }
+
+    fn input_focus(&self) -> WindowHandle {
+        let mut revert_to = 0;
+        let mut focused = 0;
+        unsafe {
+            (self.xlib.XGetInputFocus)(self.display, &mut focused, &mut revert_to);
+        }
+        focused
+    }
+
+    fn parent(&self, window: WindowHandle) -> Option<WindowHandle> {
+        let mut root = 0;
+        let mut parent = 0;
+        let mut children = ptr::null_mut();
+        let mut child_count = 0;
+        unsafe {
+            let status = (self.xlib.XQueryTree)(
+                self.display,
+                window,
+                &mut root,
+                &mut parent,
+                &mut children,
+                &mut child_count,
+            );
+            if !children.is_null() {
+                (self.xlib.XFree)(children as *mut c_void);
+            }
+
+            (status != 0 && parent != root).then_some(parent)
+        }
+    }
+}
+
+impl Keyboard for TypingInto<'_> {
+    fn key_for(&mut self, character: char) -> Result<Option<Key>, Error> {
+        let keysym = match char_keysym(character) {
+            Some(keysym) => keysym,
+            None => return Ok(None),
+        };
+
+        match self.x.keysym_key(keysym) {
+            Some(key) => Ok(Some(key)),
+            None => match self.x.borrow_keycode(keysym)? {
+                Some(key) => Ok(Some(key)),
+                None => Err(Error::NoKeycode { character }),
+            },
+        }
+    }
+
+    fn send_key(&mut self, key: Key, pressed: bool) -> Result<(), Error> {
+        self.x.send_key_event(self.window, key, pressed)
+    }
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use x11_key_remapper::key_map::{KeyMap, KeyMapParseError};
+use common::key;
+
+#[test]
+fn aliases_are_expanded_where_they_are_used() {
+    let text = "\
+let M = Ctrl+Alt
+let H = M+Shift
+define nav_left = Ctrl+43
+define up = M+111
+M+30 nav_left
+H+31 Alt+nav_left
+Super+[up 32] 9
+";
+    let key_map = KeyMap::from_stream(text.as_bytes()).unwrap();
+
+    assert_eq!(key_map.mapped_key(key("Ctrl+Alt+30")), Some(key("Ctrl+43")));
+    assert_eq!(key_map.mapped_key(key("Shift+Ctrl+Alt+31")), Some(key("Ctrl+Alt+43")));
+    assert_eq!(key_map.mapped_key(key("Ctrl+Alt+Super+111")), Some(key("9")));
+    assert_eq!(key_map.mapped_key(key("Super+32")), Some(key("9")));
+    assert_eq!(key_map.len(), 4);
+}
+
+#[test]
+fn errors_point_at_the_line_that_uses_the_alias() {
+    // the alias is fine where it is defined, but it is a whole key and
+    // can not be put in front of a list
+    let text = "define nav_left = Ctrl+43\n\n# rows\nnav_left+[30 31] 9\n";
+    let error = KeyMap::from_stream(text.as_bytes()).unwrap_err();
+    assert!(matches!(error, KeyMapParseError::NotModifier { line_number: 4 }), "{:?}", error);
+
+    let text = "let M = Ctrl+Alt\nM 9\n";
+    let error = KeyMap::from_stream(text.as_bytes()).unwrap_err();
+    assert!(matches!(error, KeyMapParseError::NoKeyPresent { line_number: 2 }), "{:?}", error);
+}
+
+#[test]
+fn unknown_aliases_are_named() {
+    let text = "define left = 43\nCtrl+rigth 9\n";
+    let error = KeyMap::from_stream(text.as_bytes()).unwrap_err();
+
+    match error {
+        KeyMapParseError::UnknownAlias { line_number, name } => {
+            assert_eq!(line_number, 2);
+            assert_eq!(name, "rigth");
+        }
+        error => panic!("{:?}", error),
+    }
+
+    // an alias is only known on the lines after its definition
+    let text = "M+30 9\nlet M = Ctrl\n";
+    assert!(matches!(
+        KeyMap::from_stream(text.as_bytes()),
+        Err(KeyMapParseError::UnknownAlias { line_number: 1, .. })
+    ));
+}
+
+#[test]
+fn broken_definitions_are_rejected() {
+    let cases = [
+        ("define Ctrl = 43\n", "shadows a modifier"),
+        ("define a = 43\ndefine a = 44\n", "defined twice"),
+        ("define a = Ctrl\n", "define without a key"),
+        ("let M = Ctrl+43\n", "let with a key"),
+        ("define 1a = 43\n", "not a name"),
+        ("define a 43\n", "no ="),
+        ("let M = Ctrl Alt\n", "too many arguments"),
+    ];
+    for (text, case) in cases {
+        assert!(KeyMap::from_stream(text.as_bytes()).is_err(), "{}", case);
+    }
+}
+
+#[test]
+fn aliases_can_not_be_named_after_a_keyword() {
+    for name in ["define", "let", "exec", "paste"] {
+        let text = format!("38 40\ndefine {} = Ctrl+43\n", name);
+        let error = KeyMap::from_stream(text.as_bytes()).unwrap_err();
+        assert!(matches!(error, KeyMapParseError::ReservedName { line_number: 2 }), "{}: {:?}", name, error);
+
+        let text = format!("let {} = Ctrl\n", name);
+        let error = KeyMap::from_stream(text.as_bytes()).unwrap_err();
+        assert!(matches!(error, KeyMapParseError::ReservedName { line_number: 1 }), "{}: {:?}", name, error);
+    }
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use x11_key_remapper::key_map::{Key, KeyMap, KeyMapParseError, Target};
+use common::key;
+
+#[test]
+fn a_key_map_can_be_built_in_code() {
+    let mut key_map = KeyMap::new();
+    assert!(key_map.is_empty());
+
+    key_map
+        .bind(key("Ctrl+43"), key("113"))
+        .bind(key("Ctrl+46"), key("114"))
+        .bind_text(key("Mod5+26"), "€");
+
+    assert_eq!(key_map.len(), 3);
+    assert_eq!(key_map.mapped_key(key("Ctrl+43")), Some(key("113")));
+    assert_eq!(key_map.target(key("Mod5+26")), Some(Target::Text("€".to_string())));
+
+    // binding a key again replaces what it was bound to
+    key_map.bind(key("Ctrl+43"), key("111"));
+    assert_eq!(key_map.mapped_key(key("Ctrl+43")), Some(key("111")));
+    assert_eq!(key_map.len(), 3);
+
+    assert_eq!(key_map.unbind(key("Ctrl+46")), Some(Target::Key(key("114"))));
+    assert_eq!(key_map.unbind(key("Ctrl+46")), None);
+    assert_eq!(key_map.mapped_key(key("Ctrl+46")), None);
+
+    // the same map as the rebind file that makes it
+    let parsed = KeyMap::from_stream("Ctrl+43 111\nMod5+26 \"€\"\n".as_bytes()).unwrap();
+    assert_eq!(key_map, parsed);
+}
+
+#[test]
+fn merged_bindings_win() {
+    let mut base = KeyMap::new();
+    base.bind(key("10"), key("20")).bind(key("11"), key("21"));
+    let mut other = KeyMap::new();
+    other.bind(key("11"), key("31")).bind(key("12"), key("32"));
+
+    base.merge(&other);
+
+    let mut bindings: Vec<(Key, Target)> = base.iter().map(|(from, to)| (from, to.clone())).collect();
+    bindings.sort_by_key(|(from, _)| from.code);
+    assert_eq!(
+        bindings,
+        vec![
+            (key("10"), Target::Key(key("20"))),
+            (key("11"), Target::Key(key("31"))),
+            (key("12"), Target::Key(key("32"))),
+        ]
+    );
+}
+
+#[test]
+fn keys_are_displayed_the_way_they_are_parsed() {
+    let keys = [
+        "9",
+        "Shift+Ctrl+Alt+Super+45",
+        "Lock+Mod2+Mod3+Mod5+Button1+Button5+113",
+        "Any+Ctrl+66",
+        "*+Shift+43",
+        "Alt+Mouse8",
+        "Ctrl+ScrollUp",
+    ];
+    for text in keys {
+        assert_eq!(key(text).to_string(), text);
+        assert_eq!(key(&key(text).to_string()), key(text));
+    }
+
+    // modifiers are written in one order, whatever order they were given in
+    assert_eq!(key("Super+Shift+10").to_string(), "Shift+Super+10");
+    assert_eq!(key("Mod4+10"), key("Super+10"));
+    assert_eq!(Key { code: 38, state: 0x5 }.to_string(), "Shift+Ctrl+38");
+}
+
+#[test]
+fn keys_that_are_not_on_a_line_report_line_0() {
+    for text in ["Ctrl+", "Ctrl+nope", "é", "Ctrl", ""] {
+        let error = text.parse::<Key>().unwrap_err();
+        let line_number = match error {
+            KeyMapParseError::NotValidKey { line_number }
+            | KeyMapParseError::NotAscii { line_number }
+            | KeyMapParseError::NoKeyPresent { line_number }
+            | KeyMapParseError::UnknownAlias { line_number, .. } => line_number,
+            error => panic!("{}: {:?}", text, error),
+        };
+        assert_eq!(line_number, 0, "{}", text);
+    }
+}
@@ -0,0 +1,73 @@
+use x11_key_remapper::key_map::{Key, KeyMap, KeyMapParseError, KeyMapWarning, Target};
+
+#[test]
+fn every_broken_line_is_reported() {
+    let text = "38 40\nFoo+3 4\n9 10\n[1 2 3\n";
+    let checked = KeyMap::check_stream(text.as_bytes()).unwrap();
+
+    assert!(matches!(
+        checked.errors[..],
+        [
+            KeyMapParseError::UnknownAlias { line_number: 2, .. },
+            KeyMapParseError::UnclosedList { line_number: 4 }
+        ]
+    ));
+    assert_eq!(checked.key_map.len(), 2);
+    assert!(KeyMap::from_stream(text.as_bytes()).is_err());
+}
+
+#[test]
+fn rebinding_a_key_is_a_warning() {
+    let key = |code| Key { code, state: 0 };
+    let text = "38 40\n38 40\n38 41\n9 9\n";
+    let checked = KeyMap::check_stream(text.as_bytes()).unwrap();
+
+    assert!(checked.errors.is_empty());
+    assert_eq!(
+        checked.warnings,
+        vec![
+            KeyMapWarning::Duplicate {
+                line_number: 2,
+                first_line: 1,
+                key: key(38)
+            },
+            KeyMapWarning::Conflict {
+                line_number: 3,
+                first_line: 2,
+                key: key(38),
+                replaced: Target::Key(key(40))
+            },
+            KeyMapWarning::Unchanged {
+                line_number: 4,
+                key: key(9)
+            },
+        ]
+    );
+    assert_eq!(
+        checked.bindings,
+        vec![(3, key(38), Target::Key(key(41))), (4, key(9), Target::Key(key(9)))]
+    );
+}
+
+#[test]
+fn structured_sections_have_the_same_warnings() {
+    let key = |code| Key { code, state: 0 };
+    let toml = "[[section]]\n[section.bindings]\n\"38\" = \"40\"\n\"9\" = \"9\"\n\"[38 39]\" = \"[41 42]\"\n";
+    let config = x11_key_remapper::config::Config::from_toml(toml).unwrap();
+
+    assert_eq!(
+        config.sections[0].warnings,
+        vec![
+            KeyMapWarning::Unchanged {
+                line_number: 4,
+                key: key(9)
+            },
+            KeyMapWarning::Conflict {
+                line_number: 5,
+                first_line: 3,
+                key: key(38),
+                replaced: Target::Key(key(40))
+            },
+        ]
+    );
+}
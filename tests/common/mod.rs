@@ -0,0 +1,36 @@
+// helpers shared by the tests, every test file only uses some of them
+#![allow(dead_code)]
+
+use x11_key_remapper::key_map::{Key, KeyMap, KeyMapParseError, Target};
+use x11_key_remapper::rebind::WindowInfo;
+use x11_key_remapper::window_type::WindowKind;
+
+pub fn key(text: &str) -> Key {
+    text.parse().unwrap()
+}
+
+pub fn key_map(text: &str) -> KeyMap {
+    KeyMap::from_stream(text.as_bytes()).unwrap()
+}
+
+// why a rebind file that is expected to be broken is
+pub fn parse_error(text: &str) -> KeyMapParseError {
+    match KeyMap::from_stream(text.as_bytes()) {
+        Ok(key_map) => panic!("{:?} parsed as {:?}", text, key_map),
+        Err(error) => error,
+    }
+}
+
+// what the key is bound to in a rebind file of a single line or more
+pub fn target(text: &str, from: &str) -> Option<Target> {
+    key_map(text).target(key(from))
+}
+
+pub fn window(class: &str, pid: u32) -> WindowInfo<'_> {
+    WindowInfo {
+        class: Some(class),
+        pid: Some(pid),
+        window_type: None,
+        kind: WindowKind::Normal,
+    }
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use x11_key_remapper::config::{Config, ConfigError, WindowFilter};
+use x11_key_remapper::key_map::{Key, KeyMap, KeyMapParseError};
+use common::{key, window};
+
+const TOML: &str = r#"
+[pause]
+window = "Super+9"
+
+[[section]]
+name = "terminal"
+match = { class = "xterm", pid = 42 }
+
+[section.let]
+M = "Ctrl+Alt"
+
+[section.bindings]
+"M+30" = "9"
+
+[[section]]
+[section.bindings]
+"Ctrl+34" = { text = "€" }
+"#;
+
+#[test]
+fn toml_sections_are_loaded_in_order() {
+    let config = Config::from_toml(TOML).unwrap();
+
+    assert_eq!(config.pause.window, Some(key("Super+9")));
+    assert_eq!(config.pause.global, None);
+    assert_eq!(config.sections.len(), 2);
+    assert_eq!(
+        config.sections[0].filter,
+        WindowFilter {
+            class: Some("xterm".to_string()),
+            pid: Some(42)
+        }
+    );
+
+    // the first section that matches is used
+    let terminal = config.section_for(&window("xterm", 42)).unwrap();
+    assert_eq!(terminal.name.as_deref(), Some("terminal"));
+    assert_eq!(terminal.key_map.mapped_key(key("Ctrl+Alt+30")), Some(key("9")));
+    assert_eq!(config.section_label_for(&window("xterm", 7)).as_deref(), Some("section 1"));
+}
+
+#[test]
+fn json_loads_the_same_config() {
+    let json = r#"{
+        "pause": { "window": "Super+9" },
+        "section": [
+            {
+                "name": "terminal",
+                "match": { "class": "xterm", "pid": 42 },
+                "let": { "M": "Ctrl+Alt" },
+                "bindings": { "M+30": "9" }
+            },
+            { "bindings": { "Ctrl+34": { "text": "€" } } }
+        ]
+    }"#;
+
+    assert_eq!(Config::from_json(json).unwrap(), Config::from_toml(TOML).unwrap());
+}
+
+#[test]
+fn later_bindings_win_in_file_order() {
+    // sorted by key the list would come last and win
+    let toml = "[[section]]\n[section.bindings]\n\"[43 44]\" = \"1\"\n\"43\" = \"2\"\n";
+    let config = Config::from_toml(toml).unwrap();
+    let key_map = &config.sections[0].key_map;
+
+    assert_eq!(key_map.mapped_key(key("43")), Some(key("2")));
+    assert_eq!(key_map.mapped_key(key("44")), Some(key("1")));
+
+    // and the converted rebind file keeps the same order
+    let rebind = "[43 44] 1\n43 2\n";
+    let source = x11_key_remapper::config::convert_rebind(rebind.as_bytes(), WindowFilter::default()).unwrap();
+    let converted = Config::from_toml(&source.to_toml()).unwrap();
+    assert_eq!(converted.sections[0].key_map, KeyMap::from_stream(rebind.as_bytes()).unwrap());
+}
+
+#[test]
+fn toml_errors_point_at_the_entry() {
+    let toml = "[[section]]\nname = \"a\"\n\n[section.bindings]\n\"Ctrl+30\" = \"9\"\n\"Ctrl+nope\" = \"9\"\n";
+    match Config::from_toml(toml) {
+        Err(ConfigError::InvalidSection { section, entry, error }) => {
+            assert_eq!(section, 0);
+            assert_eq!(entry, "Ctrl+nope");
+            assert!(matches!(error, KeyMapParseError::UnknownAlias { line_number: 6, .. }), "{:?}", error);
+        }
+        other => panic!("{:?}", other),
+    }
+
+    match Config::from_toml("[pause]\n\nglobal = \"Super+\"\n") {
+        Err(ConfigError::InvalidPauseKey { entry, error }) => {
+            assert_eq!(entry, "global");
+            assert!(matches!(error, KeyMapParseError::NotValidKey { line_number: 3 }), "{:?}", error);
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn malformed_configs_are_rejected() {
+    let tomls = [
+        "[[section]\n",
+        "[[section]]\nfilter = { class = \"x\" }\n",
+        "[[section]]\nmatch = { pid = \"x\" }\n",
+        "[[section]]\n[section.bindings]\n\"30\" = 9\n",
+        "[[section]]\n[section.bindings]\n\"30\" = { paste = \"secondary\" }\n",
+        "[[section]]\n[section.define]\na = \"Ctrl\"\n",
+        "[pause]\nlocal = \"9\"\n",
+    ];
+    for toml in tomls {
+        assert!(Config::from_toml(toml).is_err(), "{}", toml);
+    }
+
+    assert!(matches!(Config::from_toml("[[section]"), Err(ConfigError::Toml(_))));
+    assert!(matches!(Config::from_json("{\"section\": [}"), Err(ConfigError::Json(_))));
+    assert!(matches!(
+        Config::from_json("{\"section\": [{\"bindings\": {\"30\": \"x+\"}}]}"),
+        Err(ConfigError::InvalidSection { section: 0, .. })
+    ));
+}
+
+#[test]
+fn a_pid_that_is_not_a_number_is_an_error() {
+    let path = std::env::temp_dir().join(format!("x11-key-remapper-pid-{}.rebind", std::process::id()));
+    std::fs::write(&path, "30 31\n").unwrap();
+    let args = [path.to_str().unwrap().to_string(), "xterm".to_string(), "4x".to_string()];
+
+    let result = x11_key_remapper::load_config(&args);
+    std::fs::remove_file(&path).unwrap();
+    match result {
+        Err(x11_key_remapper::Error::Config(ConfigError::InvalidPid { pid })) => assert_eq!(pid, "4x"),
+        other => panic!("{:?}", other),
+    }
+
+    assert!(x11_key_remapper::load_config(&[]).is_err());
+}
+
+#[test]
+fn pause_keys_match_whatever_the_lock_modifiers() {
+    let config = Config::from_toml(TOML).unwrap();
+    let pause = key("Super+9");
+
+    for lock in [0, 0x2, 0x10, 0x12] {
+        let pressed = Key { code: pause.code, state: pause.state | lock };
+        assert!(config.pause.pauses_window(pressed), "{:?}", pressed);
+        assert!(!config.pause.pauses_all(pressed), "{:?}", pressed);
+    }
+    assert!(!config.pause.pauses_window(key("9")));
+}
@@ -0,0 +1,34 @@
+use x11_key_remapper::control::{parse_window, Command};
+
+#[test]
+fn commands_without_arguments() {
+    assert_eq!(Command::parse("list"), Ok(Command::List));
+    assert_eq!(Command::parse("  stats  "), Ok(Command::Stats));
+    assert_eq!(Command::parse("reload"), Ok(Command::Reload));
+    assert_eq!(Command::parse("enable"), Ok(Command::Enable(None)));
+    assert_eq!(Command::parse("disable"), Ok(Command::Disable(None)));
+    assert_eq!(Command::parse("layer"), Ok(Command::Layer(None)));
+}
+
+#[test]
+fn windows_are_hex_or_decimal() {
+    assert_eq!(parse_window("0x1e00007"), Ok(0x1e00007));
+    assert_eq!(parse_window("31457287"), Ok(31457287));
+    assert!(parse_window("0xzz").is_err());
+
+    assert_eq!(Command::parse("disable 0x1e00007"), Ok(Command::Disable(Some(0x1e00007))));
+    assert_eq!(Command::parse("release 42"), Ok(Command::Release(42)));
+    assert_eq!(
+        Command::parse("layer vim"),
+        Ok(Command::Layer(Some("vim".to_string())))
+    );
+}
+
+#[test]
+fn wrong_commands_are_rejected() {
+    assert!(Command::parse("").is_err());
+    assert!(Command::parse("restart").is_err());
+    assert!(Command::parse("release").is_err());
+    assert!(Command::parse("list all").is_err());
+    assert!(Command::parse("enable 1 2").is_err());
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use x11_key_remapper::child_process::Commands;
+use x11_key_remapper::key_map::{KeyMapParseError, Target};
+use common::{key, key_map, parse_error, target, window};
+
+fn exec(command: &str) -> Option<Target> {
+    Some(Target::Exec(command.to_string()))
+}
+
+// runs the command a key is bound to the way rebind does, and waits for
+// it to exit
+fn run(text: &str, from: &str, env: &[(&str, String)]) -> (u32, i32) {
+    let command = match target(text, from) {
+        Some(Target::Exec(command)) => command,
+        target => panic!("{:?} is not a command", target),
+    };
+
+    let mut commands = Commands::default();
+    let pid = commands.spawn(&command, env).unwrap();
+
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(10) {
+        if let Some(&(exited, status)) = commands.reap().first() {
+            return (exited, status.code().unwrap());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("pid {} did not exit", pid);
+}
+
+#[test]
+fn exec_targets_parse() {
+    let key_map = key_map("Ctrl+Alt+39 exec \"flameshot gui\"\n[20 21] exec xterm\n");
+
+    assert_eq!(key_map.target(key("Ctrl+Alt+39")), exec("flameshot gui"));
+    assert_eq!(key_map.target(key("21")), exec("xterm"));
+    assert_eq!(key_map.mapped_key(key("20")), None);
+}
+
+#[test]
+fn commands_are_told_the_window_they_run_for() {
+    let out = std::env::temp_dir().join(format!("x11-key-remapper-exec-{}", std::process::id()));
+    let mut env = window("XTerm", 4242).command_env(0x1a00007, ":7".to_string()).to_vec();
+    env.push(("OUT", out.display().to_string()));
+
+    let rebind = r#"Ctrl+Alt+39 exec "printf '%s %s %s %s' \"$X11_KEY_REMAPPER_CLASS\" \"$X11_KEY_REMAPPER_PID\" \"$X11_KEY_REMAPPER_WINDOW\" \"$DISPLAY\" > \"$OUT\""
+"#;
+    let (_, status) = run(rebind, "Ctrl+Alt+39", &env);
+    let written = fs::read_to_string(&out).unwrap();
+    fs::remove_file(&out).unwrap();
+
+    assert_eq!(status, 0);
+    assert_eq!(written, format!("XTerm 4242 {} :7", 0x1a00007));
+}
+
+#[test]
+fn exited_commands_are_reaped_with_their_status() {
+    let (pid, status) = run("20 exec \"exit 3\"\n", "20", &[]);
+
+    assert_ne!(pid, std::process::id());
+    assert_eq!(status, 3);
+}
+
+#[test]
+fn broken_exec_is_reported_with_what_is_wrong() {
+    assert!(matches!(parse_error("20 exec \"open\n"), KeyMapParseError::UnclosedText { line_number: 1 }));
+    assert!(matches!(parse_error("20 exec a b\n"), KeyMapParseError::TooManyArguments { line_number: 1 }));
+    assert!(matches!(parse_error("\n20 exec \"\"\n"), KeyMapParseError::NoKeyPresent { line_number: 2 }));
+}
@@ -0,0 +1,172 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::Instant;
+
+use x11_key_remapper::focus::{self, FocusBackend, FocusModel};
+use x11_key_remapper::key_map::KeyMap;
+use x11_key_remapper::window_table::WindowTable;
+use x11_key_remapper::WindowHandle;
+
+#[test]
+fn models_follow_the_input_hint_and_take_focus() {
+    assert_eq!(FocusModel::from_hints(Some(false), false), FocusModel::NoInput);
+    assert_eq!(FocusModel::from_hints(Some(true), false), FocusModel::Passive);
+    assert_eq!(FocusModel::from_hints(Some(true), true), FocusModel::LocallyActive);
+    assert_eq!(FocusModel::from_hints(Some(false), true), FocusModel::GloballyActive);
+}
+
+#[test]
+fn missing_input_hint_accepts_input() {
+    assert_eq!(FocusModel::from_hints(None, false), FocusModel::Passive);
+    assert_eq!(FocusModel::from_hints(None, true), FocusModel::LocallyActive);
+}
+
+#[test]
+fn globally_active_windows_are_only_sent_take_focus() {
+    let model = FocusModel::GloballyActive;
+    assert!(!model.sets_input_focus());
+    assert!(model.sends_take_focus());
+}
+
+#[test]
+fn no_input_windows_are_never_focused() {
+    let model = FocusModel::NoInput;
+    assert!(!model.sets_input_focus());
+    assert!(!model.sends_take_focus());
+}
+
+#[test]
+fn locally_active_windows_get_both() {
+    let model = FocusModel::LocallyActive;
+    assert!(model.sets_input_focus());
+    assert!(model.sends_take_focus());
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Request {
+    SetInputFocus { window: WindowHandle, time: u64 },
+    TakeFocus { window: WindowHandle, time: u64 },
+}
+
+// a window tree with a focus, that records the focus requests sent to it
+#[derive(Default)]
+struct MockBackend {
+    models: HashMap<WindowHandle, FocusModel>,
+    parents: HashMap<WindowHandle, WindowHandle>,
+    focused: Cell<WindowHandle>,
+    requests: RefCell<Vec<Request>>,
+}
+
+impl FocusBackend for MockBackend {
+    fn focus_model(&self, window: WindowHandle) -> FocusModel {
+        self.models.get(&window).copied().unwrap_or(FocusModel::Passive)
+    }
+
+    fn set_input_focus(&self, window: WindowHandle, time: u64) {
+        self.focused.set(window);
+        self.requests.borrow_mut().push(Request::SetInputFocus { window, time });
+    }
+
+    fn send_take_focus(&self, window: WindowHandle, time: u64) {
+        self.requests.borrow_mut().push(Request::TakeFocus { window, time });
+    }
+
+    fn input_focus(&self) -> WindowHandle {
+        self.focused.get()
+    }
+
+    fn parent(&self, window: WindowHandle) -> Option<WindowHandle> {
+        self.parents.get(&window).copied()
+    }
+}
+
+// parent 100 wraps child 10, and the child has a subwindow 20
+fn wrapped(model: FocusModel) -> (MockBackend, WindowTable) {
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 10, KeyMap::new(), Instant::now());
+    windows.wrap(100);
+
+    let backend = MockBackend {
+        models: HashMap::from([(10, model)]),
+        parents: HashMap::from([(10, 100), (20, 10)]),
+        focused: Cell::new(100),
+        ..MockBackend::default()
+    };
+    (backend, windows)
+}
+
+#[test]
+fn take_focus_on_a_parent_is_passed_to_the_child_with_its_time() {
+    let (backend, windows) = wrapped(FocusModel::LocallyActive);
+
+    assert_eq!(focus::pass_focus(&backend, &windows, 100, Some(4242)), Some(10));
+    assert_eq!(
+        *backend.requests.borrow(),
+        vec![
+            Request::SetInputFocus { window: 10, time: 4242 },
+            Request::TakeFocus { window: 10, time: 4242 },
+        ]
+    );
+    assert!(focus::has_focus_within(&backend, 10));
+}
+
+#[test]
+fn globally_active_children_are_only_forwarded_the_message() {
+    let (backend, windows) = wrapped(FocusModel::GloballyActive);
+
+    assert_eq!(focus::pass_focus(&backend, &windows, 100, Some(7)), Some(10));
+    assert_eq!(*backend.requests.borrow(), vec![Request::TakeFocus { window: 10, time: 7 }]);
+    // the child takes the focus itself when it wants to
+    assert_eq!(backend.input_focus(), 100);
+}
+
+#[test]
+fn parent_focus_without_a_time_uses_current_time() {
+    let (backend, windows) = wrapped(FocusModel::Passive);
+
+    assert_eq!(focus::pass_focus(&backend, &windows, 100, None), Some(10));
+    assert_eq!(*backend.requests.borrow(), vec![Request::SetInputFocus { window: 10, time: 0 }]);
+}
+
+#[test]
+fn focus_inside_the_child_is_left_alone() {
+    let (backend, windows) = wrapped(FocusModel::LocallyActive);
+
+    for focused in [10, 20] {
+        backend.focused.set(focused);
+        assert!(focus::has_focus_within(&backend, 10));
+        assert_eq!(focus::pass_focus(&backend, &windows, 100, Some(1)), None);
+    }
+    assert!(backend.requests.borrow().is_empty());
+}
+
+#[test]
+fn focus_elsewhere_is_not_within_the_child() {
+    let (backend, _) = wrapped(FocusModel::Passive);
+
+    // the parent itself, another top level window, no focus and PointerRoot
+    for focused in [100, 300, 0, 1] {
+        backend.focused.set(focused);
+        assert!(!focus::has_focus_within(&backend, 10));
+    }
+}
+
+#[test]
+fn windows_that_are_not_wrapped_are_not_focused() {
+    let (backend, windows) = wrapped(FocusModel::Passive);
+
+    assert_eq!(focus::pass_focus(&backend, &windows, 300, Some(1)), None);
+    assert!(backend.requests.borrow().is_empty());
+}
+
+#[test]
+fn subwindows_are_within_the_child() {
+    let (backend, _) = wrapped(FocusModel::Passive);
+
+    // a selection requestor can be the child or one of its subwindows,
+    // but not the parent or another client's window
+    assert!(focus::is_within(&backend, 10, 10));
+    assert!(focus::is_within(&backend, 20, 10));
+    assert!(!focus::is_within(&backend, 100, 10));
+    assert!(!focus::is_within(&backend, 300, 10));
+}
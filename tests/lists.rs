@@ -0,0 +1,53 @@
+mod common;
+
+use x11_key_remapper::key_map::{KeyMap, KeyMapParseError};
+use common::key;
+
+#[test]
+fn lists_are_paired_in_order() {
+    let key_map = KeyMap::from_stream("Alt+[10 11  12] Ctrl+[Shift+20 21 22]\n".as_bytes()).unwrap();
+
+    assert_eq!(key_map.len(), 3);
+    assert_eq!(key_map.mapped_key(key("Alt+10")), Some(key("Shift+Ctrl+20")));
+    assert_eq!(key_map.mapped_key(key("Alt+11")), Some(key("Ctrl+21")));
+    assert_eq!(key_map.mapped_key(key("Alt+12")), Some(key("Ctrl+22")));
+}
+
+#[test]
+fn a_single_target_is_shared() {
+    let key_map = KeyMap::from_stream("Super+[30 31 32] 9\n".as_bytes()).unwrap();
+
+    for from in ["Super+30", "Super+31", "Super+32"] {
+        assert_eq!(key_map.mapped_key(key(from)), Some(key("9")));
+    }
+}
+
+#[test]
+fn lists_of_different_lengths_are_rejected() {
+    let text = "38 40\n[10 11 12] [20 21]\n";
+    assert!(matches!(
+        KeyMap::from_stream(text.as_bytes()),
+        Err(KeyMapParseError::ListLengthMismatch { line_number: 2 })
+    ));
+}
+
+#[test]
+fn unclosed_lists_are_rejected() {
+    for text in ["[10 11 20\n", "10 Alt+[20 21\n"] {
+        assert!(
+            matches!(
+                KeyMap::from_stream(text.as_bytes()),
+                Err(KeyMapParseError::UnclosedList { line_number: 1 })
+            ),
+            "{}",
+            text
+        );
+    }
+}
+
+#[test]
+fn broken_lists_are_rejected() {
+    for text in ["[] 9\n", "[10] 11] 9\n", "[10 [11]] 9\n", "Alt[10] 9\n", "[10]+Alt 9\n"] {
+        assert!(KeyMap::from_stream(text.as_bytes()).is_err(), "{}", text);
+    }
+}
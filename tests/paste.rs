@@ -0,0 +1,97 @@
+mod common;
+
+use x11_key_remapper::key_map::{KeyMap, KeyMapParseError, Paste, Selection, Target, Transform};
+use common::{key, key_map, parse_error};
+
+fn paste(key_map: &KeyMap, from: &str) -> Paste {
+    match key_map.target(key(from)) {
+        Some(Target::Paste(paste)) => paste,
+        target => panic!("{:?} is not a paste", target),
+    }
+}
+
+#[test]
+fn paste_targets_parse() {
+    let rebind = "define v = 55\nCtrl+Shift+v paste clipboard trim replace \"\\\\s+\" \" \" with Ctrl+Shift+v\n20 paste primary\n";
+    let key_map = key_map(rebind);
+
+    assert_eq!(
+        paste(&key_map, "Ctrl+Shift+55"),
+        Paste {
+            selection: Selection::Clipboard,
+            transforms: vec![
+                Transform::Trim,
+                Transform::Replace {
+                    pattern: "\\s+".to_string(),
+                    replacement: " ".to_string()
+                },
+            ],
+            key: Some(key("Ctrl+Shift+55")),
+        }
+    );
+    assert_eq!(paste(&key_map, "20").selection, Selection::Primary);
+    assert_eq!(key_map.mapped_key(key("20")), None);
+}
+
+#[test]
+fn transforms_run_in_order() {
+    let key_map = key_map("20 paste clipboard plain trim replace \"(\\\\w+)@(\\\\w+)\" \"$2 at $1\"\n");
+
+    let text = "\u{feff} me@example\u{a0}here\r\n\u{200b}";
+    assert_eq!(paste(&key_map, "20").apply(text), "example at me here");
+}
+
+#[test]
+fn trim_only_takes_space_off_the_ends() {
+    let key_map = key_map("20 paste clipboard trim\n");
+
+    assert_eq!(paste(&key_map, "20").apply("\n\t  a  b \r\n"), "a  b");
+    assert_eq!(paste(&key_map, "20").apply("   "), "");
+}
+
+#[test]
+fn plain_takes_the_formatting_out_of_copied_text() {
+    let key_map = key_map("20 paste clipboard plain\n");
+
+    let copied = "line\u{a0}one\r\nline\u{2003}two\rsoft\u{ad}hyphen\u{200d}\u{7}\tend";
+    assert_eq!(paste(&key_map, "20").apply(copied), "line one\nline two\nsofthyphen\tend");
+}
+
+#[test]
+fn replace_changes_every_match() {
+    let key_map = key_map("20 paste primary replace \"(\\\\d+)-(\\\\d+)\" \"$2/$1\" replace \"/\" \" / \"\n");
+
+    assert_eq!(paste(&key_map, "20").apply("1-2, 30-40"), "2 / 1, 40 / 30");
+}
+
+#[test]
+fn the_order_of_the_transforms_matters() {
+    let key_map = key_map("20 paste clipboard replace \"^\" \"> \" trim\n21 paste clipboard trim replace \"^\" \"> \"\n");
+
+    assert_eq!(paste(&key_map, "20").apply("  quote"), ">   quote");
+    assert_eq!(paste(&key_map, "21").apply("  quote"), "> quote");
+}
+
+#[test]
+fn broken_paste_is_reported_with_what_is_wrong() {
+    assert!(matches!(parse_error("20 paste\n"), KeyMapParseError::TooFewArguments { line_number: 1 }));
+    assert!(matches!(parse_error("20 paste secondary\n"), KeyMapParseError::UnknownSelection { line_number: 1 }));
+    assert!(matches!(parse_error("20 paste clipboard upper\n"), KeyMapParseError::UnknownTransform { line_number: 1 }));
+    assert!(matches!(
+        parse_error("20 paste clipboard replace \"a\"\n"),
+        KeyMapParseError::TooFewArguments { line_number: 1 }
+    ));
+    assert!(matches!(
+        parse_error("\n20 paste clipboard replace \"(\" \"\"\n"),
+        KeyMapParseError::InvalidPattern { line_number: 2 }
+    ));
+    assert!(matches!(
+        parse_error("20 paste clipboard with 55 trim\n"),
+        KeyMapParseError::TooManyArguments { line_number: 1 }
+    ));
+    // the key that pastes is sent, so it can not be a wildcard
+    assert!(matches!(
+        parse_error("20 paste clipboard with Any+55\n"),
+        KeyMapParseError::NotValidKey { line_number: 1 }
+    ));
+}
@@ -0,0 +1,91 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use x11_key_remapper::reactor::{Reactor, Wakeup};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("x11-key-remapper-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn timers_fire_in_the_order_they_are_due() {
+    // nothing is ever written to the other end, so only the timers wake it
+    let (connection, _server) = UnixStream::pair().unwrap();
+    let mut reactor = Reactor::new(connection.as_raw_fd());
+    reactor.add_timer(Duration::from_millis(30), "second");
+    reactor.add_timer(Duration::from_millis(5), "first");
+
+    assert!(matches!(reactor.wait().unwrap(), Wakeup::Timer("first")));
+    assert!(matches!(reactor.wait().unwrap(), Wakeup::Timer("second")));
+}
+
+#[test]
+fn readable_connection_wakes_for_x_events() {
+    let (connection, mut server) = UnixStream::pair().unwrap();
+    let mut reactor: Reactor<()> = Reactor::new(connection.as_raw_fd());
+    server.write_all(b"event").unwrap();
+
+    assert!(matches!(reactor.wait().unwrap(), Wakeup::XEvents));
+}
+
+#[test]
+fn replaced_file_is_reported_once() {
+    let dir = scratch_dir("watch");
+    let path = dir.join("config.toml");
+    fs::write(&path, "").unwrap();
+
+    let (connection, _server) = UnixStream::pair().unwrap();
+    let mut reactor: Reactor<()> = Reactor::new(connection.as_raw_fd());
+    reactor.watch_file(&path).unwrap();
+
+    // the way most editors save, a new file is moved over the old one
+    let new_path = dir.join("config.toml.new");
+    fs::write(&new_path, "[[section]]").unwrap();
+    fs::rename(&new_path, &path).unwrap();
+    fs::write(dir.join("unrelated"), "").unwrap();
+    reactor.add_timer(Duration::from_millis(50), ());
+
+    match reactor.wait().unwrap() {
+        Wakeup::FileChanged(changed) => assert_eq!(changed, path.canonicalize().unwrap()),
+        wakeup => panic!("expected a file change, got {:?}", wakeup),
+    }
+    assert!(matches!(reactor.wait().unwrap(), Wakeup::Timer(())));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn control_connections_are_accepted() {
+    let dir = scratch_dir("control");
+    let path = dir.join("control.sock");
+
+    let (connection, _server) = UnixStream::pair().unwrap();
+    let mut reactor: Reactor<()> = Reactor::new(connection.as_raw_fd());
+    reactor.listen(UnixListener::bind(&path).unwrap()).unwrap();
+
+    let _client = UnixStream::connect(&path).unwrap();
+    assert!(matches!(reactor.wait().unwrap(), Wakeup::Control(_)));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn caught_signals_are_read_instead_of_delivered() {
+    let (connection, _server) = UnixStream::pair().unwrap();
+    let mut reactor: Reactor<()> = Reactor::new(connection.as_raw_fd());
+    reactor.catch_signals(&[libc::SIGUSR2]).unwrap();
+
+    // the signal is blocked on this thread only, so it is sent to it alone
+    unsafe {
+        libc::pthread_kill(libc::pthread_self(), libc::SIGUSR2);
+    }
+
+    assert!(matches!(reactor.wait().unwrap(), Wakeup::Signal(libc::SIGUSR2)));
+}
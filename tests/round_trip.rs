@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use x11_key_remapper::config::{Config, TargetSource};
+use x11_key_remapper::key_map::KeyMap;
+
+fn round_trip(key_map: &KeyMap) -> KeyMap {
+    let text = key_map.to_rebind();
+    KeyMap::from_stream(text.as_bytes()).unwrap()
+}
+
+#[test]
+fn example_rebind_round_trips() {
+    let file = BufReader::new(File::open("examples/firefox.rebind").unwrap());
+    let key_map = KeyMap::from_stream(file).unwrap();
+
+    assert_eq!(round_trip(&key_map), key_map);
+}
+
+#[test]
+fn aliases_and_lists_round_trip() {
+    let text = "\
+let M = Ctrl+Alt
+define nav_left = Ctrl+43
+M+[30 31 32] Alt+[10 11 12]
+Super+Shift+nav_left Lock+Mod2+Mod3+Mod5+113
+Button1+Button5+9 Mod4+9
+";
+    let key_map = KeyMap::from_stream(text.as_bytes()).unwrap();
+
+    assert_eq!(round_trip(&key_map), key_map);
+}
+
+#[test]
+fn targets_of_every_kind_round_trip() {
+    let text = r#"Super+20 "Best regards,\n\t\"me\" \\o/"
+Ctrl+Alt+39 exec "notify-send \"$X11_KEY_REMAPPER_CLASS\""
+Shift+Ctrl+55 paste clipboard trim plain replace "a\"b" "\\n" with Shift+Ctrl+55
+Mouse8 paste primary
+"#;
+    let key_map = KeyMap::from_stream(text.as_bytes()).unwrap();
+
+    assert_eq!(round_trip(&key_map), key_map);
+
+    let config = Config::single(Default::default(), key_map);
+    let source = config.to_source();
+    assert_eq!(Config::from_toml(&source.to_toml()).unwrap(), config);
+    assert_eq!(Config::from_json(&source.to_json()).unwrap(), config);
+}
+
+#[test]
+fn serialized_text_is_canonical() {
+    let text = "Alt+Ctrl+45 111\nShift+9 Ctrl+Shift+10\n";
+    let key_map = KeyMap::from_stream(text.as_bytes()).unwrap();
+
+    assert_eq!(key_map.to_rebind(), "Shift+9 Shift+Ctrl+10\nCtrl+Alt+45 111\n");
+    assert_eq!(round_trip(&key_map).to_rebind(), key_map.to_rebind());
+}
+
+#[test]
+fn structured_config_round_trips() {
+    let config = Config::from_path("examples/firefox.toml").unwrap();
+    let source = config.to_source();
+
+    assert_eq!(Config::from_toml(&source.to_toml()).unwrap(), config);
+    assert_eq!(Config::from_json(&source.to_json()).unwrap(), config);
+}
+
+#[test]
+fn pause_keys_are_loaded() {
+    let config = Config::from_path("examples/firefox.toml").unwrap();
+
+    assert_eq!(config.pause.window, Some("Super+9".parse().unwrap()));
+    assert_eq!(config.pause.global, Some("Super+Shift+9".parse().unwrap()));
+    assert!(Config::from_toml("[pause]\nwindow = \"Super+nope\"\n").is_err());
+}
+
+#[test]
+fn wildcards_round_trip() {
+    let text = "Any+66 9\n*+Ctrl+43 113\nlet W = *+Alt\nW+[30 31] [10 11]\n";
+    let key_map = KeyMap::from_stream(text.as_bytes()).unwrap();
+
+    assert_eq!(round_trip(&key_map), key_map);
+    assert_eq!(
+        key_map.to_rebind(),
+        "*+Alt+30 10\n*+Alt+31 11\n*+Ctrl+43 113\nAny+66 9\n"
+    );
+}
+
+#[test]
+fn keycodes_are_written_with_their_names() {
+    let text = "Ctrl+38 9\n10 Shift+38\nSuper+11 12\n13 paste clipboard with Ctrl+38\n";
+    let key_map = KeyMap::from_stream(text.as_bytes()).unwrap();
+
+    // 11 has no name, 12 has a name that is a number, and the two that
+    // share a name can not both be written with it
+    let name_of = |code| match code {
+        9 => Some("Escape".to_string()),
+        10 => Some("1".to_string()),
+        12 | 13 => Some("same".to_string()),
+        38 => Some("a".to_string()),
+        _ => None,
+    };
+    let names = key_map.code_names(name_of);
+    let named = key_map.to_rebind_named(&names);
+
+    assert_eq!(
+        named,
+        "define Escape = 9\ndefine a = 38\n10 Shift+a\nSuper+11 12\n13 paste clipboard with Ctrl+a\nCtrl+a Escape\n"
+    );
+    assert_eq!(KeyMap::from_stream(named.as_bytes()).unwrap(), key_map);
+
+    let config = Config::single(Default::default(), key_map);
+    let source = config.to_source_named(name_of);
+    assert_eq!(source.sections[0].define["a"], "38");
+    assert_eq!(source.sections[0].bindings["Ctrl+a"], TargetSource::Key("Escape".to_string()));
+    assert_eq!(Config::from_toml(&source.to_toml()).unwrap(), config);
+}
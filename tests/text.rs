@@ -0,0 +1,109 @@
+mod common;
+
+use std::collections::HashMap;
+
+use x11_key_remapper::key_map::{Key, KeyMapParseError, Target};
+use x11_key_remapper::typing::{self, Keyboard};
+use x11_key_remapper::Error;
+use common::{key, key_map, parse_error, target};
+
+fn text(text: &str) -> Option<Target> {
+    Some(Target::Text(text.to_string()))
+}
+
+// a keyboard with a few characters on it and spare keycodes for the
+// rest, which records the events that are typed
+struct MockKeyboard {
+    keys: HashMap<char, Key>,
+    spare: Vec<u32>,
+    events: Vec<(Key, bool)>,
+}
+
+impl MockKeyboard {
+    fn new(spare: &[u32]) -> MockKeyboard {
+        MockKeyboard {
+            keys: HashMap::from([('a', key("38")), ('A', key("Shift+38")), (' ', key("65")), ('\n', key("36"))]),
+            spare: spare.to_vec(),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl Keyboard for MockKeyboard {
+    fn key_for(&mut self, character: char) -> Result<Option<Key>, Error> {
+        if character.is_control() && character != '\n' {
+            return Ok(None);
+        }
+        if let Some(&key) = self.keys.get(&character) {
+            return Ok(Some(key));
+        }
+        if self.spare.is_empty() {
+            return Err(Error::NoKeycode { character });
+        }
+
+        let key = Key { code: self.spare.remove(0), state: 0 };
+        self.keys.insert(character, key);
+        Ok(Some(key))
+    }
+
+    fn send_key(&mut self, key: Key, pressed: bool) -> Result<(), Error> {
+        self.events.push((key, pressed));
+        Ok(())
+    }
+}
+
+// the events of pressing and releasing every key in turn
+fn taps(keys: &[&str]) -> Vec<(Key, bool)> {
+    keys.iter().flat_map(|&text| [(key(text), true), (key(text), false)]).collect()
+}
+
+#[test]
+fn quoted_targets_are_text() {
+    let key_map = key_map("Mod5+26 \"€\"\nSuper+[20 21] \"— [x] \\\"y\\\"\\n\"\n");
+
+    assert_eq!(key_map.target(key("Mod5+26")), text("€"));
+    assert_eq!(key_map.target(key("Super+21")), text("— [x] \"y\"\n"));
+    assert_eq!(key_map.mapped_key(key("Mod5+26")), None);
+}
+
+#[test]
+fn every_character_is_pressed_and_released() {
+    let to_type = match target("Super+20 \"Aa a\\n\"\n", "Super+20") {
+        Some(Target::Text(text)) => text,
+        target => panic!("{:?} is not text", target),
+    };
+
+    let mut keyboard = MockKeyboard::new(&[]);
+    typing::type_text(&mut keyboard, &to_type).unwrap();
+    assert_eq!(keyboard.events, taps(&["Shift+38", "38", "65", "38", "36"]));
+}
+
+#[test]
+fn characters_that_are_not_on_the_keyboard_use_spare_keycodes() {
+    let mut keyboard = MockKeyboard::new(&[200, 201]);
+    typing::type_text(&mut keyboard, "€a€—\u{7}").unwrap();
+
+    // the bell has no key and is left out
+    assert_eq!(keyboard.events, taps(&["200", "38", "200", "201"]));
+}
+
+#[test]
+fn typing_stops_at_the_first_character_without_a_keycode() {
+    let mut keyboard = MockKeyboard::new(&[200]);
+
+    match typing::type_text(&mut keyboard, "a€£a") {
+        Err(Error::NoKeycode { character: '£' }) => (),
+        result => panic!("{:?}", result),
+    }
+    assert_eq!(keyboard.events, taps(&["38", "200"]));
+}
+
+#[test]
+fn broken_text_is_reported_with_what_is_wrong() {
+    assert!(matches!(parse_error("20 \"open\n"), KeyMapParseError::UnclosedText { line_number: 1 }));
+    assert!(matches!(parse_error("20 \"\\q\"\n"), KeyMapParseError::InvalidEscape { line_number: 1 }));
+    assert!(matches!(parse_error("20 \"a\"b\n"), KeyMapParseError::CouldNotParse { line_number: 1 }));
+    assert!(matches!(parse_error("\n20 \"\"\n"), KeyMapParseError::NoKeyPresent { line_number: 2 }));
+    // text is only something a key is bound to
+    assert!(matches!(parse_error("\"a\" 20\n"), KeyMapParseError::NotValidKey { line_number: 1 }));
+}
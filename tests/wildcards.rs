@@ -0,0 +1,67 @@
+mod common;
+
+use x11_key_remapper::key_map::{Key, KeyMap};
+use common::{key, key_map};
+
+#[test]
+fn any_ignores_the_other_modifiers() {
+    let key_map = key_map("Any+66 9\n");
+
+    assert_eq!(key_map.mapped_key(key("66")), Some(key("9")));
+    assert_eq!(key_map.mapped_key(key("Ctrl+Shift+66")), Some(key("9")));
+    assert_eq!(key_map.mapped_key(key("65")), None);
+}
+
+#[test]
+fn star_keeps_the_other_modifiers() {
+    let key_map = key_map("*+Ctrl+43 113\n");
+
+    assert_eq!(key_map.mapped_key(key("Ctrl+43")), Some(key("113")));
+    assert_eq!(key_map.mapped_key(key("Ctrl+Shift+43")), Some(key("Shift+113")));
+    assert_eq!(key_map.mapped_key(key("Shift+43")), None);
+}
+
+#[test]
+fn the_most_specific_binding_wins() {
+    let key_map = key_map("Any+43 1\nAny+Ctrl+43 2\n*+Ctrl+43 3\nCtrl+Shift+43 4\n");
+
+    assert_eq!(key_map.mapped_key(key("Alt+43")), Some(key("1")));
+    assert_eq!(key_map.mapped_key(key("Ctrl+Alt+43")), Some(key("2")));
+    assert_eq!(key_map.mapped_key(key("Ctrl+Shift+43")), Some(key("4")));
+}
+
+#[test]
+fn ties_between_wildcards_always_go_the_same_way() {
+    // whichever order they are bound in, Shift comes before Ctrl
+    for text in ["*+Ctrl+43 1\n*+Shift+43 2\n", "*+Shift+43 2\n*+Ctrl+43 1\n"] {
+        let key_map = key_map(text);
+        for _ in 0..16 {
+            assert_eq!(key_map.mapped_key(key("Ctrl+Shift+43")), Some(key("Ctrl+2")));
+        }
+    }
+
+    let key_map = key_map("*+Ctrl+43 1\nAny+Ctrl+43 2\n");
+    assert_eq!(key_map.mapped_key(key("Ctrl+Alt+43")), Some(key("2")));
+}
+
+#[test]
+fn wildcards_are_only_allowed_on_pressed_keys() {
+    assert!(KeyMap::from_stream("43 Any+113\n".as_bytes()).is_err());
+    assert!(KeyMap::from_stream("Any+*+43 113\n".as_bytes()).is_err());
+    assert!(KeyMap::from_stream("define Any = 43\n".as_bytes()).is_err());
+}
+
+#[test]
+fn buttons_are_keys_of_their_own() {
+    let key_map = key_map("Mouse8 Alt+113\nCtrl+ScrollUp Ctrl+21\nSuper+38 Mouse2\nAny+Mouse9 Mouse3\n");
+
+    assert_eq!(key_map.mapped_key(key("Mouse8")), Some(key("Alt+113")));
+    assert_eq!(key_map.mapped_key(key("8")), None);
+    assert_eq!(key_map.mapped_key(key("Ctrl+Mouse4")), Some(key("Ctrl+21")));
+    assert_eq!(key_map.mapped_key(key("Super+38")), Some(key("Mouse2")));
+    assert_eq!(key_map.mapped_key(key("Shift+Mouse9")), Some(key("Mouse3")));
+    assert!(key("Mouse2").is_button());
+    assert_eq!(key("ScrollDown").to_string(), "ScrollDown");
+    assert_eq!(key("Shift+Mouse8").to_string(), "Shift+Mouse8");
+    assert!("Mouse0".parse::<Key>().is_err());
+}
@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use x11_key_remapper::key_map::KeyMap;
+use x11_key_remapper::window_table::{WindowState, WindowTable};
+
+const PENDING_TIMEOUT: Duration = Duration::from_secs(5);
+const EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn parents_exposed_out_of_order_get_their_own_child() {
+    let now = Instant::now();
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, KeyMap::new(), now);
+    windows.add_pending(200, 2, KeyMap::new(), now);
+
+    assert_eq!(windows.wrap(200), Some(2));
+    assert_eq!(windows.wrap(100), Some(1));
+    assert_eq!(windows.wrapped_child(100), Some(1));
+    assert_eq!(windows.wrapped_parent(2), Some(200));
+}
+
+#[test]
+fn unrelated_expose_does_not_take_a_child() {
+    let now = Instant::now();
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, KeyMap::new(), now);
+
+    assert_eq!(windows.wrap(300), None);
+    assert_eq!(windows.wrap(100), Some(1));
+}
+
+#[test]
+fn second_expose_does_not_wrap_again() {
+    let now = Instant::now();
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, KeyMap::new(), now);
+
+    assert_eq!(windows.wrap(100), Some(1));
+    assert_eq!(windows.wrap(100), None);
+    assert_eq!(windows.state(100), Some(WindowState::Wrapped(1)));
+}
+
+#[test]
+fn each_parent_keeps_its_own_key_map() {
+    let now = Instant::now();
+    let mut first = KeyMap::new();
+    first.bind("Ctrl+45".parse().unwrap(), "111".parse().unwrap());
+
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, first.clone(), now);
+    windows.add_pending(200, 2, KeyMap::new(), now);
+
+    // the key map only applies once the parent is wrapped
+    assert_eq!(windows.key_map(100), None);
+
+    windows.wrap(200);
+    windows.wrap(100);
+    assert_eq!(windows.key_map(100), Some(&first));
+    assert_eq!(windows.key_map(200), Some(&KeyMap::new()));
+}
+
+#[test]
+fn known_children_are_not_wrapped_twice() {
+    let now = Instant::now();
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, KeyMap::new(), now);
+
+    assert!(windows.has_child(1));
+    assert!(!windows.has_child(2));
+
+    windows.wrap(100);
+    windows.start_exit(100, now);
+    assert!(windows.has_child(1));
+}
+
+#[test]
+fn pending_parents_time_out_and_release_their_child() {
+    let now = Instant::now();
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, KeyMap::new(), now);
+    windows.add_pending(200, 2, KeyMap::new(), now + Duration::from_secs(3));
+
+    let expired = windows.expire(now + PENDING_TIMEOUT, PENDING_TIMEOUT, EXIT_TIMEOUT);
+    assert_eq!(expired, vec![(100, WindowState::Pending { child: 1, since: now })]);
+    assert_eq!(windows.state(100), Some(WindowState::Released(1)));
+    assert!(windows.has_child(1));
+
+    // a late expose of the released parent does nothing
+    assert_eq!(windows.wrap(100), None);
+    assert_eq!(windows.wrap(200), Some(2));
+}
+
+#[test]
+fn exiting_children_time_out() {
+    let now = Instant::now();
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, KeyMap::new(), now);
+    windows.wrap(100);
+    windows.start_exit(100, now);
+
+    assert!(windows.expire(now + Duration::from_secs(1), PENDING_TIMEOUT, EXIT_TIMEOUT).is_empty());
+
+    let expired = windows.expire(now + EXIT_TIMEOUT, PENDING_TIMEOUT, EXIT_TIMEOUT);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(windows.state(100), None);
+    assert!(!windows.has_child(1));
+}
+
+#[test]
+fn exiting_entry_waits_for_the_child_to_be_destroyed() {
+    let now = Instant::now();
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, KeyMap::new(), now);
+    windows.wrap(100);
+    windows.start_exit(100, now);
+
+    // the parent is destroyed right away, the child only once it closed
+    assert_eq!(windows.remove_destroyed(100), None);
+    assert_eq!(
+        windows.remove_destroyed(1),
+        Some((100, WindowState::Exiting { child: 1, since: now }))
+    );
+}
+
+#[test]
+fn destroyed_child_removes_its_wrapped_parent() {
+    let now = Instant::now();
+    let mut windows = WindowTable::new();
+    windows.add_pending(100, 1, KeyMap::new(), now);
+    windows.add_pending(200, 2, KeyMap::new(), now);
+    windows.wrap(100);
+    windows.wrap(200);
+
+    assert_eq!(windows.remove_destroyed(1), Some((100, WindowState::Wrapped(1))));
+    assert_eq!(windows.wrapped_child(200), Some(2));
+    assert_eq!(windows.wrapped().collect::<Vec<_>>(), vec![(200, 2)]);
+}